@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::daemon::Event;
+use crate::process::{Outcome, Report};
+
+/// What happened to an archive, as written to the audit log.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    Detected,
+    Extracted,
+    /// The extracted files were found in the destination.
+    Verified,
+    VerificationFailed,
+    Duplicate,
+    Planned,
+    Failed,
+    RetryScheduled,
+    Quarantined,
+    Removed,
+}
+
+/// One line of the audit log.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// RFC 3339 timestamp.
+    pub time: String,
+    /// Shared by all records about one archive, from its detection until it
+    /// has left the watched folder.
+    pub id: u64,
+    pub archive: PathBuf,
+    pub profile: String,
+    pub stage: Stage,
+    /// Destination, error or retry delay, depending on the stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IdOnly {
+    id: u64,
+}
+
+struct Lifecycle {
+    id: u64,
+    profile: String,
+    /// Queued or with a worker, so more records are coming.
+    busy: bool,
+    retrying: bool,
+    /// Removed from or moved out of the watched folder.
+    gone: bool,
+}
+
+/// Follows every archive through the daemon and appends what happens to it,
+/// one JSON object per line, to a file that is never truncated.
+pub struct AuditLog {
+    file: File,
+    next_id: u64,
+    archives: HashMap<PathBuf, Lifecycle>,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it if needed. Ids continue
+    /// after the highest one already in the file.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let last_id = BufReader::new(&file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<IdOnly>(&line).ok())
+            .map(|record| record.id)
+            .max()
+            .unwrap_or(0);
+        Ok(AuditLog {
+            file,
+            next_id: last_id + 1,
+            archives: HashMap::new(),
+        })
+    }
+
+    /// Records what `event` means for the archive it is about.
+    pub fn record(&mut self, event: &Event) -> io::Result<()> {
+        match event {
+            Event::Detected { path, profile } => {
+                if self.archives.get(path).is_some_and(|a| a.busy && !a.gone) {
+                    return Ok(());
+                }
+                let id = self.next_id;
+                self.next_id += 1;
+                self.archives.insert(
+                    path.clone(),
+                    Lifecycle {
+                        id,
+                        profile: profile.clone(),
+                        busy: true,
+                        retrying: false,
+                        gone: false,
+                    },
+                );
+                self.write(path, Stage::Detected, None)
+            }
+            Event::Finished { path, result } => {
+                match result {
+                    Ok(report) => self.finished(path, report)?,
+                    Err(e) => self.write(path, Stage::Failed, Some(e.to_string()))?,
+                }
+                if let Some(archive) = self.archives.get_mut(path) {
+                    archive.busy = std::mem::take(&mut archive.retrying);
+                }
+                self.forget_if_done(path);
+                Ok(())
+            }
+            Event::RetryScheduled { path, attempt, delay } => {
+                if let Some(archive) = self.archives.get_mut(path) {
+                    archive.retrying = true;
+                }
+                let detail = format!("attempt {} in {}s", attempt, delay.as_secs_f64());
+                self.write(path, Stage::RetryScheduled, Some(detail))
+            }
+            Event::Quarantined { path, to } => {
+                self.write(path, Stage::Quarantined, Some(to.display().to_string()))?;
+                self.mark_gone(path);
+                Ok(())
+            }
+            Event::Removed(path) => {
+                if self.archives.get(path).is_none_or(|a| a.gone) {
+                    return Ok(());
+                }
+                self.write(path, Stage::Removed, None)?;
+                self.mark_gone(path);
+                Ok(())
+            }
+            Event::WatchLost(_) | Event::WatchRestored(_) => Ok(()),
+        }
+    }
+
+    fn finished(&mut self, path: &Path, report: &Report) -> io::Result<()> {
+        match &report.outcome {
+            Outcome::Extracted(dir) => {
+                self.write(path, Stage::Extracted, Some(dir.display().to_string()))?;
+                match verify(report) {
+                    Ok(()) => self.write(path, Stage::Verified, None),
+                    Err(why) => self.write(path, Stage::VerificationFailed, Some(why)),
+                }
+            }
+            Outcome::Duplicate(existing) => self.write(path, Stage::Duplicate, Some(existing.display().to_string())),
+            Outcome::Planned(dir) => self.write(path, Stage::Planned, Some(dir.display().to_string())),
+        }
+    }
+
+    fn mark_gone(&mut self, path: &Path) {
+        if let Some(archive) = self.archives.get_mut(path) {
+            archive.gone = true;
+        }
+        self.forget_if_done(path);
+    }
+
+    /// Ends the lifecycle once the archive is gone and no worker will report on it.
+    fn forget_if_done(&mut self, path: &Path) {
+        if self.archives.get(path).is_some_and(|a| a.gone && !a.busy) {
+            self.archives.remove(path);
+        }
+    }
+
+    fn write(&mut self, path: &Path, stage: Stage, detail: Option<String>) -> io::Result<()> {
+        let (id, profile) = match self.archives.get(path) {
+            Some(archive) => (archive.id, archive.profile.clone()),
+            None => (0, String::new()),
+        };
+        let record = AuditRecord {
+            time: chrono::Local::now().to_rfc3339(),
+            id,
+            archive: path.to_path_buf(),
+            profile,
+            stage,
+            detail,
+        };
+        let mut line = serde_json::to_string(&record).map_err(io::Error::other)?;
+        line.push('\n');
+        // A single write per record keeps lines whole even if another
+        // process appends to the same file.
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+/// Checks that the destination of an extraction holds at least the files the
+/// extractor reported writing.
+fn verify(report: &Report) -> Result<(), String> {
+    let Outcome::Extracted(dir) = &report.outcome else {
+        return Ok(());
+    };
+    if !dir.is_dir() {
+        return Err(format!("{} does not exist", dir.display()));
+    }
+    let written = report.extraction.as_ref().map_or(0, |e| e.entries_written);
+    let found = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .count();
+    if found < written {
+        return Err(format!("found {} of {} extracted files in {}", found, written, dir.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractors::{ExtractError, ExtractReport};
+    use crate::process::ProcessError;
+    use std::time::Duration;
+
+    fn read_log(path: &Path) -> Vec<AuditRecord> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_audit_log_follows_archives() {
+        let td = std::env::temp_dir().join(format!("unzipper_audit_test_{}", std::process::id()));
+        fs::create_dir_all(td.join("a")).unwrap();
+        fs::write(td.join("a/file.txt"), b"x").unwrap();
+        let log_path = td.join("logs/audit.jsonl");
+        let mut log = AuditLog::open(&log_path).unwrap();
+        let a = td.join("a.zip");
+        let b = td.join("b.zip");
+        let detected = |path: &Path| Event::Detected {
+            path: path.to_path_buf(),
+            profile: String::from("downloads"),
+        };
+
+        log.record(&detected(&a)).unwrap();
+        log.record(&detected(&a)).unwrap();
+        log.record(&detected(&b)).unwrap();
+        // The worker deletes the archive before it reports back.
+        log.record(&Event::Removed(a.clone())).unwrap();
+        let mut extraction = ExtractReport::new("zip", &a);
+        extraction.entries_written = 1;
+        log.record(&Event::Finished {
+            path: a.clone(),
+            result: Ok(Report {
+                archive: a.clone(),
+                format: String::from("zip"),
+                outcome: Outcome::Extracted(td.join("a")),
+                extraction: Some(extraction),
+            }),
+        })
+        .unwrap();
+        log.record(&Event::RetryScheduled {
+            path: b.clone(),
+            attempt: 2,
+            delay: Duration::from_secs(2),
+        })
+        .unwrap();
+        log.record(&Event::Finished {
+            path: b.clone(),
+            result: Err(ProcessError::Extract(ExtractError::Cancelled)),
+        })
+        .unwrap();
+        log.record(&Event::Quarantined {
+            path: b.clone(),
+            to: td.join("q/b.zip"),
+        })
+        .unwrap();
+        log.record(&Event::Finished {
+            path: b.clone(),
+            result: Err(ProcessError::Extract(ExtractError::Cancelled)),
+        })
+        .unwrap();
+        // A new archive under a known name starts a new lifecycle.
+        log.record(&detected(&a)).unwrap();
+        assert_eq!(log.archives.len(), 1);
+        drop(log);
+
+        let records = read_log(&log_path);
+        let stages: Vec<(u64, Stage)> = records.iter().map(|r| (r.id, r.stage)).collect();
+        assert_eq!(
+            stages,
+            vec![
+                (1, Stage::Detected),
+                (2, Stage::Detected),
+                (1, Stage::Removed),
+                (1, Stage::Extracted),
+                (1, Stage::Verified),
+                (2, Stage::RetryScheduled),
+                (2, Stage::Failed),
+                (2, Stage::Quarantined),
+                (2, Stage::Failed),
+                (3, Stage::Detected),
+            ]
+        );
+        assert!(records.iter().all(|r| r.profile == "downloads"));
+
+        // Reopening appends.
+        let mut log = AuditLog::open(&log_path).unwrap();
+        log.record(&detected(&b)).unwrap();
+        let records = read_log(&log_path);
+        assert_eq!(records.len(), 11);
+        assert_eq!(records[10].id, 4);
+        fs::remove_dir_all(&td).ok();
+    }
+}
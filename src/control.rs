@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::extractors::Progress;
+
+#[cfg(unix)]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+#[cfg(unix)]
+use std::time::Duration;
+#[cfg(unix)]
+use std::{fs, thread};
+
+/// A command sent to a running daemon, one JSON object per line, e.g.
+/// `{"command":"enqueue","path":"/home/me/Downloads/a.zip"}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    /// Hands no further archives to the workers; running extractions go on.
+    Pause,
+    Resume,
+    /// Queues an archive in one of the watched folders.
+    Enqueue { path: PathBuf },
+    /// Moves a quarantined archive back to where it was found and queues it.
+    Retry { path: PathBuf },
+    Rescan,
+    Stop,
+}
+
+/// The daemon's answer to a [`Request`], one JSON object per line.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Response {
+    Status(Status),
+    Done(String),
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    pub paused: bool,
+    /// Archives waiting for a worker.
+    pub queued: usize,
+    pub running: Vec<Running>,
+}
+
+/// An archive a worker is busy with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Running {
+    pub worker: usize,
+    pub archive: PathBuf,
+    pub profile: String,
+    pub elapsed_secs: f64,
+    /// `None` until the extraction itself has started.
+    pub progress: Option<Progress>,
+}
+
+/// `$XDG_RUNTIME_DIR/unzipper.sock`, or a per-user name in the temporary
+/// folder where that isn't set.
+#[cfg(unix)]
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("unzipper.sock"),
+        _ => {
+            let user = std::env::var("USER").unwrap_or_default();
+            std::env::temp_dir().join(format!("unzipper-{}.sock", user))
+        }
+    }
+}
+
+/// Listens on `path`, replacing a socket left behind by a daemon that is gone.
+/// Only the current user may connect.
+#[cfg(unix)]
+pub(crate) fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Another daemon is listening on {}", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Answers requests on `listener` with `handle` until `shutting_down` is set,
+/// then removes the socket at `path`. Each client is served on its own
+/// thread, so one that stays connected doesn't hold up the others.
+#[cfg(unix)]
+pub(crate) fn serve(
+    listener: UnixListener,
+    path: PathBuf,
+    shutting_down: Arc<AtomicBool>,
+    handle: impl Fn(Request) -> Response + Send + Sync + 'static,
+) {
+    let handle = Arc::new(handle);
+    while !shutting_down.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let handle = Arc::clone(&handle);
+                thread::spawn(move || {
+                    if let Err(e) = serve_client(stream, &*handle) {
+                        if !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) {
+                            eprintln!("[Control] Error talking to a client: {}", e);
+                        }
+                    }
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(100)),
+            Err(e) => {
+                eprintln!("[Control] Error accepting a connection: {}", e);
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+    let _ = fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+fn serve_client(stream: UnixStream, handle: &impl Fn(Request) -> Response) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => handle(request),
+            Err(e) => Response::Error(format!("Invalid request: {}", e)),
+        };
+        let mut json = serde_json::to_string(&response).map_err(io::Error::other)?;
+        json.push('\n');
+        writer.write_all(json.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Sends `request` to the daemon listening on `socket` and waits for its answer.
+#[cfg(unix)]
+pub fn send(socket: &Path, request: &Request) -> io::Result<Response> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut json = serde_json::to_string(request).map_err(io::Error::other)?;
+    json.push('\n');
+    stream.write_all(json.as_bytes())?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(io::Error::other)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_socket_round_trip() {
+        let path = std::env::temp_dir().join(format!("unzipper_control_test_{}.sock", std::process::id()));
+        let listener = bind(&path).unwrap();
+        assert_eq!(bind(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let server = {
+            let path = path.clone();
+            let shutting_down = Arc::clone(&shutting_down);
+            thread::spawn(move || {
+                serve(listener, path, shutting_down, |request| match request {
+                    Request::Status => Response::Status(Status {
+                        queued: 2,
+                        ..Status::default()
+                    }),
+                    Request::Enqueue { path } => Response::Done(format!("Queued {}", path.display())),
+                    _ => Response::Error(String::from("unsupported")),
+                })
+            })
+        };
+
+        // A client that connects and says nothing doesn't keep others waiting.
+        let idle = UnixStream::connect(&path).unwrap();
+        let started = std::time::Instant::now();
+        let status = send(&path, &Request::Status).unwrap();
+        assert!(matches!(status, Response::Status(Status { queued: 2, .. })));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(idle);
+        let enqueue = Request::Enqueue {
+            path: PathBuf::from("/tmp/a.zip"),
+        };
+        assert_eq!(send(&path, &enqueue).unwrap(), Response::Done(String::from("Queued /tmp/a.zip")));
+        assert_eq!(
+            serde_json::to_string(&enqueue).unwrap(),
+            r#"{"command":"enqueue","path":"/tmp/a.zip"}"#
+        );
+
+        shutting_down.store(true, Ordering::SeqCst);
+        server.join().unwrap();
+        assert!(!path.exists());
+    }
+}
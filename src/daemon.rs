@@ -0,0 +1,1269 @@
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, PollWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+use crate::audit::AuditLog;
+#[cfg(unix)]
+use crate::control::{self, Request, Response, Running, Status};
+use crate::dispatch::{self, Job};
+use crate::extractors::{CancelToken, ExtractError, Progress, ProgressSink};
+use crate::process::{process_file, ProcessError, Report};
+use crate::profile::Profile;
+use crate::quarantine;
+use crate::retry::{self, RetryPolicy};
+use crate::scheduler::{self, LargeArchives, Scheduler, WorkerPool};
+
+/// Something the daemon did, passed to the callbacks registered with
+/// [`DaemonBuilder::on_event`].
+#[derive(Debug)]
+pub enum Event {
+    /// An archive was found in a watched folder and queued for a worker.
+    Detected { path: PathBuf, profile: String },
+    /// A worker is done with an archive.
+    Finished {
+        path: PathBuf,
+        result: Result<Report, ProcessError>,
+    },
+    /// An archive failed for a reason that may go away and will be tried again.
+    RetryScheduled {
+        path: PathBuf,
+        attempt: u32,
+        delay: Duration,
+    },
+    /// An archive that failed to extract was moved out of the way.
+    Quarantined { path: PathBuf, to: PathBuf },
+    /// An archive disappeared from a watched folder.
+    Removed(PathBuf),
+    /// A watched folder went away, or now points elsewhere; it is watched
+    /// again once it is back.
+    WatchLost(PathBuf),
+    /// A lost folder is back and watched again.
+    WatchRestored(PathBuf),
+}
+
+/// Archives waiting for a worker before the dispatcher holds back new ones.
+const QUEUE_CAPACITY: usize = 256;
+
+type Callback = Box<dyn Fn(&Event) + Send + Sync>;
+
+fn emit(callbacks: &[Callback], event: Event) {
+    for callback in callbacks {
+        callback(&event);
+    }
+}
+
+/// Processes one job, then schedules a retry for transient failures or
+/// quarantines archives that are broken. Archives that failed for other
+/// reasons, e.g. a missing tool or a read-only destination, stay where they
+/// are; returns `true` for those.
+fn handle_job(
+    job: Job,
+    worker_id: usize,
+    retry: &RetryPolicy,
+    tx_retry: &mpsc::Sender<(Instant, Job)>,
+    callbacks: &[Callback],
+    cancel: &CancelToken,
+    progress: &ProgressSink,
+) -> bool {
+    let result = process_file(&job.path, &job.profile, worker_id, cancel, progress);
+    let failures = job.failures + 1;
+    let mut left_in_place = false;
+    match &result {
+        Err(e) if e.is_transient() && retry.delay(failures).is_some() => {
+            let delay = retry.delay(failures).expect("checked above");
+            println!(
+                "[Worker {}] Will retry {} in {}s (attempt {} of {}): {}",
+                worker_id,
+                job.path.display(),
+                delay.as_secs_f64(),
+                failures + 1,
+                retry.max_attempts,
+                e
+            );
+            emit(
+                callbacks,
+                Event::RetryScheduled {
+                    path: job.path.clone(),
+                    attempt: failures + 1,
+                    delay,
+                },
+            );
+            let _ = tx_retry.send((
+                Instant::now() + delay,
+                job.retry(),
+            ));
+        }
+        Err(ProcessError::Extract(ExtractError::Cancelled)) => {}
+        Err(ProcessError::Extract(e)) if e.is_archive_fault() => match quarantine::quarantine(&job.path, &job.profile, e, worker_id) {
+            Ok(to) => emit(
+                callbacks,
+                Event::Quarantined {
+                    path: job.path.clone(),
+                    to,
+                },
+            ),
+            Err(qe) => {
+                eprintln!(
+                    "[Worker {}] Could not quarantine {}: {}",
+                    worker_id,
+                    job.path.display(),
+                    qe
+                );
+                left_in_place = true;
+            }
+        },
+        Err(e) => {
+            eprintln!(
+                "[Worker {}] Leaving {} in place: {}",
+                worker_id,
+                job.path.display(),
+                e
+            );
+            left_in_place = true;
+        }
+        Ok(_) => {}
+    }
+    emit(
+        callbacks,
+        Event::Finished {
+            path: job.path.clone(),
+            result,
+        },
+    );
+    left_in_place
+}
+
+/// The profile watching the folder `path` is in. Both sides are resolved, so
+/// relative or symlinked watch paths match the paths events carry.
+fn profile_for(profiles: &[Arc<Profile>], path: &Path) -> Option<Arc<Profile>> {
+    let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+    let parent = canonical(path.parent()?);
+    profiles.iter().find(|p| canonical(&p.watch_path) == parent).cloned()
+}
+
+/// Whether `path` lies in the quarantine folder of one of `profiles`.
+fn in_quarantine(profiles: &[Arc<Profile>], path: &Path) -> bool {
+    let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+    let Some(parent) = path.parent() else {
+        return false;
+    };
+    let parent = canonical(parent);
+    profiles.iter().any(|p| canonical(&quarantine::dir_for(p, path)) == parent)
+}
+
+fn is_processable_archive_extension(ext: &str) -> bool {
+    matches!(
+        ext.to_ascii_lowercase().as_str(),
+        "zip" | "rar" | "7z" | "tar" | "gz"
+    )
+}
+
+fn has_archive_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(is_processable_archive_extension)
+}
+
+fn is_processable_path(path: &Path) -> bool {
+    path.is_file() && has_archive_extension(path)
+}
+
+/// Turns watcher events into jobs.
+#[derive(Clone)]
+struct WatchHandler {
+    profiles: Vec<Arc<Profile>>,
+    callbacks: Arc<Vec<Callback>>,
+    tx_jobs: mpsc::Sender<Job>,
+    scanner: Arc<Mutex<Scanner>>,
+    rescan_requested: Arc<AtomicBool>,
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl WatchHandler {
+    /// Hands `path` to the dispatcher. Returns false once the daemon is
+    /// shutting down and no longer takes jobs.
+    fn queue(&self, path: PathBuf, profile: Arc<Profile>) -> bool {
+        self.scanner.lock().unwrap().note(&path);
+        emit(
+            &self.callbacks,
+            Event::Detected {
+                path: path.clone(),
+                profile: profile.name.clone(),
+            },
+        );
+        if let Err(mpsc::SendError(job)) = self.tx_jobs.send(Job::new(path, profile)) {
+            eprintln!("[Main] Shutting down. Not queueing {}.", job.path.display());
+            return false;
+        }
+        true
+    }
+
+    /// Reports an archive that left a watched folder. It no longer exists,
+    /// so only its name tells whether it was one.
+    fn removed(&self, path: PathBuf) {
+        if !has_archive_extension(&path) || profile_for(&self.profiles, &path).is_none() {
+            return;
+        }
+        println!("[Main] Confirmed file deletion via event: {}", path.display());
+        emit(&self.callbacks, Event::Removed(path));
+    }
+}
+
+impl notify::EventHandler for WatchHandler {
+    fn handle_event(&mut self, res: notify::Result<notify::Event>) {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return;
+        }
+        match res {
+            // The kernel queue overflowed, so events were lost.
+            Ok(event) if event.need_rescan() => {
+                eprintln!("[Main] The watcher dropped events. Rescanning watched folders.");
+                self.rescan_requested.store(true, Ordering::SeqCst);
+            }
+            Ok(event) => match event.kind {
+                // A file written under its final name fires Create when it is
+                // opened, so closing it after writing counts as arriving too.
+                EventKind::Create(_)
+                | EventKind::Modify(notify::event::ModifyKind::Name(_))
+                | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                    for path in event.paths {
+                        if !path.exists() {
+                            // Renamed away, or gone again before we got to it.
+                            self.removed(path);
+                            continue;
+                        }
+                        if !is_processable_path(&path) {
+                            continue;
+                        }
+                        let Some(profile) = profile_for(&self.profiles, &path) else {
+                            continue;
+                        };
+                        println!(
+                            "[Main] Detected file event for: {}. Sending to worker.",
+                            path.display()
+                        );
+                        self.queue(path, profile);
+                    }
+                }
+                // Download tools like aria2 finish by removing their sidecar file.
+                EventKind::Remove(_) => {
+                    for sidecar in event.paths {
+                        self.removed(sidecar.clone());
+                        let Some(profile) = profile_for(&self.profiles, &sidecar) else {
+                            continue;
+                        };
+                        let Some(path) = profile.partial_rules().download_of(&sidecar) else {
+                            continue;
+                        };
+                        if !is_processable_path(&path) {
+                            continue;
+                        }
+                        println!(
+                            "[Main] {} finished downloading. Sending to worker.",
+                            path.display()
+                        );
+                        self.queue(path, profile);
+                    }
+                }
+                _ => (),
+            },
+            Err(e) => {
+                eprintln!("[Main] Watch error: {:?}. Rescanning watched folders.", e);
+                self.rescan_requested.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// The watcher for `profile`'s folder: native events, or polling where they
+/// don't arrive for changes made by other hosts.
+fn watcher_for(profile: &Profile, handler: WatchHandler) -> notify::Result<Box<dyn Watcher>> {
+    if !profile.polls() {
+        return Ok(Box::new(notify::recommended_watcher(handler)?));
+    }
+    println!(
+        "[Main] Polling {} every {}s.",
+        profile.watch_path.display(),
+        profile.poll_interval
+    );
+    let config = notify::Config::default()
+        .with_poll_interval(Duration::from_secs(profile.poll_interval.max(1)))
+        .with_compare_contents(profile.poll_compare_contents);
+    Ok(Box::new(PollWatcher::new(handler, config)?))
+}
+
+struct Current {
+    archive: PathBuf,
+    profile: String,
+    started: Instant,
+    progress: Option<Progress>,
+}
+
+/// Archives the workers are busy with.
+#[derive(Default)]
+struct InFlight(Mutex<BTreeMap<usize, Current>>);
+
+impl InFlight {
+    /// Marks `worker` busy with `job` until the returned guard is dropped,
+    /// also when the worker panics.
+    fn start(&self, worker: usize, job: &Job) -> InFlightGuard<'_> {
+        let current = Current {
+            archive: job.path.clone(),
+            profile: job.profile.name.clone(),
+            started: Instant::now(),
+            progress: None,
+        };
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).insert(worker, current);
+        InFlightGuard { in_flight: self, worker }
+    }
+
+    /// Passes on the progress of whatever `worker` is extracting.
+    fn progress_sink(self: &Arc<Self>, worker: usize) -> ProgressSink {
+        let in_flight = Arc::clone(self);
+        ProgressSink::new(move |progress| {
+            let mut jobs = in_flight.0.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(current) = jobs.get_mut(&worker) {
+                current.progress = Some(*progress);
+            }
+        })
+    }
+
+    #[cfg(unix)]
+    fn snapshot(&self) -> Vec<Running> {
+        let jobs = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.iter()
+            .map(|(&worker, current)| Running {
+                worker,
+                archive: current.archive.clone(),
+                profile: current.profile.clone(),
+                elapsed_secs: current.started.elapsed().as_secs_f64(),
+                progress: current.progress,
+            })
+            .collect()
+    }
+}
+
+struct InFlightGuard<'a> {
+    in_flight: &'a InFlight,
+    worker: usize,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.0.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.worker);
+    }
+}
+
+/// What the control socket acts on.
+#[cfg(unix)]
+struct Controls {
+    handler: WatchHandler,
+    scheduler: Arc<Scheduler>,
+    paused: Arc<AtomicBool>,
+    in_flight: Arc<InFlight>,
+}
+
+#[cfg(unix)]
+impl Controls {
+    fn answer(&self, request: Request) -> Response {
+        match request {
+            Request::Status => Response::Status(Status {
+                paused: self.paused.load(Ordering::SeqCst),
+                queued: self.scheduler.queued(),
+                running: self.in_flight.snapshot(),
+            }),
+            Request::Pause => {
+                if self.paused.swap(true, Ordering::SeqCst) {
+                    return Response::Done(String::from("Already paused"));
+                }
+                println!("[Main] Paused. Running extractions will finish; nothing new is started.");
+                Response::Done(String::from("Paused"))
+            }
+            Request::Resume => {
+                if !self.paused.swap(false, Ordering::SeqCst) {
+                    return Response::Done(String::from("Not paused"));
+                }
+                println!("[Main] Resumed.");
+                Response::Done(String::from("Resumed"))
+            }
+            Request::Enqueue { path } => {
+                if !path.is_file() {
+                    return Response::Error(format!("No such file: {}", path.display()));
+                }
+                if !is_processable_path(&path) {
+                    return Response::Error(format!("Not a supported archive: {}", path.display()));
+                }
+                let Some(profile) = profile_for(&self.handler.profiles, &path) else {
+                    return Response::Error(format!("Not in a watched folder: {}", path.display()));
+                };
+                if !self.handler.queue(path.clone(), profile) {
+                    return Response::Error(String::from("Daemon is shutting down"));
+                }
+                println!("[Main] Queued {} on request.", path.display());
+                Response::Done(format!("Queued {}", path.display()))
+            }
+            Request::Retry { path } => {
+                if !in_quarantine(&self.handler.profiles, &path) {
+                    return Response::Error(format!("Not in a quarantine folder: {}", path.display()));
+                }
+                if self.handler.shutting_down.load(Ordering::SeqCst) {
+                    return Response::Error(String::from("Daemon is shutting down"));
+                }
+                let original = match quarantine::restore(&path) {
+                    Ok(original) => original,
+                    Err(e) => return Response::Error(format!("Could not restore {}: {}", path.display(), e)),
+                };
+                println!("[Main] Moved {} back to {}.", path.display(), original.display());
+                match profile_for(&self.handler.profiles, &original) {
+                    Some(profile) if is_processable_path(&original) => {
+                        if !self.handler.queue(original.clone(), profile) {
+                            return Response::Error(format!(
+                                "Moved back to {}, but the daemon is shutting down",
+                                original.display()
+                            ));
+                        }
+                        Response::Done(format!("Moved back to {} and queued", original.display()))
+                    }
+                    _ => Response::Done(format!("Moved back to {}, which is not watched", original.display())),
+                }
+            }
+            Request::Rescan => {
+                self.handler.rescan_requested.store(true, Ordering::SeqCst);
+                Response::Done(String::from("Rescanning watched folders"))
+            }
+            Request::Stop => {
+                if self.handler.shutting_down.swap(true, Ordering::SeqCst) {
+                    return Response::Done(String::from("Already shutting down"));
+                }
+                eprintln!("[Main] Stop requested. Shutting down gracefully...");
+                Response::Done(String::from("Shutting down"))
+            }
+        }
+    }
+}
+
+/// Identity of a watched folder: where it resolves to and, on Unix, the
+/// directory itself, so a folder recreated under the same name is noticed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FolderId {
+    path: PathBuf,
+    #[cfg(unix)]
+    inode: (u64, u64),
+}
+
+fn folder_id(path: &Path) -> Option<FolderId> {
+    let resolved = std::fs::canonicalize(path).ok()?;
+    let metadata = std::fs::metadata(&resolved).ok()?;
+    if !metadata.is_dir() {
+        return None;
+    }
+    Some(FolderId {
+        path: resolved,
+        #[cfg(unix)]
+        inode: {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.dev(), metadata.ino())
+        },
+    })
+}
+
+/// Brings the watch on the folder of `profile` in line with `now`, what the
+/// folder is at this moment, given that `current` is what is watched. Returns
+/// `true` once it is watched anew, so it should be rescanned.
+fn refresh_watch(
+    profile: &Profile,
+    current: &mut Option<FolderId>,
+    now: Option<FolderId>,
+    watcher: &mut Option<Box<dyn Watcher>>,
+    handler: &WatchHandler,
+) -> bool {
+    if now == *current {
+        return false;
+    }
+    if current.take().is_some() {
+        *watcher = None;
+        eprintln!(
+            "[Main] Lost the watch on {}. Waiting for it to come back...",
+            profile.watch_path.display()
+        );
+        emit(&handler.callbacks, Event::WatchLost(profile.watch_path.clone()));
+    }
+    if now.is_none() {
+        return false;
+    }
+    // Set up anew: the folder may now live on another filesystem.
+    let restored = watcher_for(profile, handler.clone()).and_then(|mut w| {
+        w.watch(&profile.watch_path, RecursiveMode::NonRecursive)?;
+        Ok(w)
+    });
+    let Ok(restored) = restored else {
+        return false;
+    };
+    *watcher = Some(restored);
+    println!("[Main] Watching directory: {} again.", profile.watch_path.display());
+    emit(&handler.callbacks, Event::WatchRestored(profile.watch_path.clone()));
+    *current = now;
+    true
+}
+
+/// Remembers the archives seen in the watched folders, so a rescan only
+/// queues the ones that showed up since.
+#[derive(Default)]
+struct Scanner {
+    seen: HashSet<PathBuf>,
+}
+
+impl Scanner {
+    /// Records `path` as seen, e.g. because the watcher reported it.
+    fn note(&mut self, path: &Path) {
+        self.seen.insert(path.to_path_buf());
+    }
+
+    /// Lets the next scan report `path` again, e.g. because its job failed
+    /// and left it where it was.
+    fn forget(&mut self, path: &Path) {
+        self.seen.remove(path);
+    }
+
+    /// Archives in `dir` that weren't there on the last scan.
+    fn scan(&mut self, dir: &Path) -> Vec<PathBuf> {
+        let mut found = Vec::new();
+        let mut present = HashSet::new();
+        for entry in WalkDir::new(dir).max_depth(1).into_iter().filter_map(Result::ok) {
+            let path = entry.path();
+            if !is_processable_path(path) {
+                continue;
+            }
+            present.insert(path.to_path_buf());
+            if self.seen.insert(path.to_path_buf()) {
+                found.push(path.to_path_buf());
+            }
+        }
+        self.seen.retain(|path| path.parent() != Some(dir) || present.contains(path));
+        found
+    }
+}
+
+/// Queues the archives `scanner` finds in the folders of `profiles`.
+/// Returns how many there were.
+fn scan_folders(
+    profiles: &[Arc<Profile>],
+    scanner: &mut Scanner,
+    callbacks: &[Callback],
+    tx_jobs: &mpsc::Sender<Job>,
+) -> usize {
+    let mut queued = 0;
+    for profile in profiles {
+        for path in scanner.scan(&profile.watch_path) {
+            println!(
+                "[Main] Found existing archive: {}. Sending to worker.",
+                path.display()
+            );
+            emit(
+                callbacks,
+                Event::Detected {
+                    path: path.clone(),
+                    profile: profile.name.clone(),
+                },
+            );
+            if tx_jobs.send(Job::new(path, Arc::clone(profile))).is_err() {
+                eprintln!("[Main] Shutting down. Not queueing any more archives.");
+                return queued;
+            }
+            queued += 1;
+        }
+    }
+    queued
+}
+
+/// Configures a [`Daemon`].
+pub struct DaemonBuilder {
+    defaults: Profile,
+    watch_paths: Vec<PathBuf>,
+    profiles: Vec<Profile>,
+    workers: usize,
+    retry: RetryPolicy,
+    debounce: Duration,
+    shutdown_grace: Duration,
+    large: LargeArchives,
+    rescan_interval: Option<Duration>,
+    audit_log: Option<PathBuf>,
+    #[cfg(unix)]
+    control_socket: Option<PathBuf>,
+    callbacks: Vec<Callback>,
+}
+
+impl DaemonBuilder {
+    /// Policies for the folders added with [`DaemonBuilder::watch`].
+    pub fn policies(mut self, defaults: Profile) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// Watches `path` with the policies set by [`DaemonBuilder::policies`].
+    pub fn watch(mut self, path: impl Into<PathBuf>) -> Self {
+        self.watch_paths.push(path.into());
+        self
+    }
+
+    /// Watches `profile.watch_path` with the profile's own policies.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profiles.push(profile);
+        self
+    }
+
+    /// Number of archives extracted in parallel; defaults to
+    /// [`scheduler::default_workers`].
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// How archives failing for transient reasons are retried.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Which archives count as large and how many of them may be extracted
+    /// at once. Large archives never take every worker.
+    pub fn large_archives(mut self, large: LargeArchives) -> Self {
+        self.large = large;
+        self
+    }
+
+    /// How long a shutdown waits for running extractions to finish before
+    /// cancelling them and removing their partial output.
+    pub fn shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    /// Also looks through the watched folders every `interval`, in case the
+    /// watcher missed an archive. Off by default; the folders are rescanned
+    /// anyway whenever the watcher reports lost events.
+    pub fn rescan_every(mut self, interval: Duration) -> Self {
+        self.rescan_interval = Some(interval);
+        self
+    }
+
+    /// Appends the lifecycle of every archive to `path`; see [`AuditLog`].
+    pub fn audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.audit_log = Some(path.into());
+        self
+    }
+
+    /// Accepts [`control::Request`]s on a Unix socket at `path`, e.g.
+    /// [`control::default_socket_path`].
+    #[cfg(unix)]
+    pub fn control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    /// How long events for an archive have to settle before it is queued.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Calls `callback` for every [`Event`], from whichever thread it happens on.
+    pub fn on_event<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Fails if nothing is watched, a watched folder doesn't exist, or the
+    /// audit log or control socket can't be opened.
+    pub fn build(self) -> io::Result<Daemon> {
+        let mut profiles = self.profiles;
+        for path in self.watch_paths {
+            profiles.push(Profile {
+                name: path.display().to_string(),
+                watch_path: path,
+                ..self.defaults.clone()
+            });
+        }
+        if profiles.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No folder to watch"));
+        }
+        for profile in &profiles {
+            if !profile.watch_path.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Watch directory does not exist: {}", profile.watch_path.display()),
+                ));
+            }
+        }
+        let mut callbacks = self.callbacks;
+        if let Some(path) = &self.audit_log {
+            let log = Mutex::new(AuditLog::open(path)?);
+            callbacks.push(Box::new(move |event| {
+                if let Err(e) = log.lock().unwrap().record(event) {
+                    eprintln!("[Main] Could not write to the audit log: {}", e);
+                }
+            }));
+        }
+        #[cfg(unix)]
+        let control = match self.control_socket {
+            Some(path) => Some((control::bind(&path)?, path)),
+            None => None,
+        };
+        Ok(Daemon {
+            profiles: profiles.into_iter().map(Arc::new).collect(),
+            workers: self.workers,
+            retry: self.retry,
+            debounce: self.debounce,
+            shutdown_grace: self.shutdown_grace,
+            large: self.large,
+            rescan_interval: self.rescan_interval,
+            callbacks: Arc::new(callbacks),
+            #[cfg(unix)]
+            control,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+/// Stops a running [`Daemon`] from another thread.
+#[derive(Clone)]
+pub struct DaemonHandle(Arc<AtomicBool>);
+
+impl DaemonHandle {
+    /// Asks the daemon to shut down; returns `false` if that was already requested.
+    pub fn stop(&self) -> bool {
+        !self.0.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// Watches folders and extracts the archives that show up in them.
+pub struct Daemon {
+    profiles: Vec<Arc<Profile>>,
+    workers: usize,
+    retry: RetryPolicy,
+    debounce: Duration,
+    shutdown_grace: Duration,
+    large: LargeArchives,
+    rescan_interval: Option<Duration>,
+    callbacks: Arc<Vec<Callback>>,
+    #[cfg(unix)]
+    control: Option<(UnixListener, PathBuf)>,
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl Daemon {
+    pub fn builder() -> DaemonBuilder {
+        DaemonBuilder {
+            defaults: Profile::default(),
+            watch_paths: Vec::new(),
+            profiles: Vec::new(),
+            workers: scheduler::default_workers(),
+            retry: RetryPolicy::default(),
+            debounce: Duration::from_millis(500),
+            shutdown_grace: Duration::from_secs(10),
+            large: LargeArchives::default(),
+            rescan_interval: None,
+            audit_log: None,
+            #[cfg(unix)]
+            control_socket: None,
+            callbacks: Vec::new(),
+        }
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = &Profile> {
+        self.profiles.iter().map(|p| p.as_ref())
+    }
+
+    pub fn handle(&self) -> DaemonHandle {
+        DaemonHandle(Arc::clone(&self.shutting_down))
+    }
+
+    /// Extracts the archives already present, then watches for new ones until
+    /// [`DaemonHandle::stop`] is called.
+    pub fn run(self) -> notify::Result<()> {
+        let profiles = self.profiles;
+        let callbacks = self.callbacks;
+        let shutting_down = self.shutting_down;
+        let scheduler = Scheduler::new(QUEUE_CAPACITY, self.workers, self.large);
+        let tx_jobs = dispatch::spawn_dispatcher(Arc::clone(&scheduler), self.debounce, Arc::clone(&shutting_down));
+        let tx_retry = retry::spawn_delay_queue(tx_jobs.clone(), Arc::clone(&shutting_down));
+        let scanner = Arc::new(Mutex::new(Scanner::default()));
+        let rescan_requested = Arc::new(AtomicBool::new(false));
+        let handler = WatchHandler {
+            profiles: profiles.clone(),
+            callbacks: Arc::clone(&callbacks),
+            tx_jobs: tx_jobs.clone(),
+            scanner: Arc::clone(&scanner),
+            rescan_requested: Arc::clone(&rescan_requested),
+            shutting_down: Arc::clone(&shutting_down),
+        };
+        // Watches come first: if one can't be set up, nothing has been
+        // queued or started that would go on without the daemon.
+        let mut watchers = Vec::new();
+        for profile in &profiles {
+            println!(
+                "[Main] Watching directory: {} for new archives...",
+                profile.watch_path.display()
+            );
+            let watcher = watcher_for(profile, handler.clone()).and_then(|mut w| {
+                w.watch(&profile.watch_path, RecursiveMode::NonRecursive)?;
+                Ok(w)
+            });
+            match watcher {
+                Ok(watcher) => watchers.push(Some(watcher)),
+                Err(e) => {
+                    shutting_down.store(true, Ordering::SeqCst);
+                    scheduler.close();
+                    #[cfg(unix)]
+                    if let Some((_, path)) = &self.control {
+                        let _ = fs::remove_file(path);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let worker_scheduler = Arc::clone(&scheduler);
+        let worker_sd = Arc::clone(&shutting_down);
+        let worker_callbacks = Arc::clone(&callbacks);
+        let worker_tx_retry = tx_retry.clone();
+        let retry = self.retry;
+        let cancel = CancelToken::new();
+        let worker_cancel = cancel.clone();
+        let paused = Arc::new(AtomicBool::new(false));
+        let worker_paused = Arc::clone(&paused);
+        let in_flight = Arc::new(InFlight::default());
+        let worker_in_flight = Arc::clone(&in_flight);
+        let worker_scanner = Arc::clone(&scanner);
+        let mut pool = WorkerPool::start(self.workers, move |i| {
+            let scheduler = Arc::clone(&worker_scheduler);
+            let sd = Arc::clone(&worker_sd);
+            let callbacks = Arc::clone(&worker_callbacks);
+            let tx_retry = worker_tx_retry.clone();
+            let cancel = worker_cancel.clone();
+            let paused = Arc::clone(&worker_paused);
+            let in_flight = Arc::clone(&worker_in_flight);
+            let progress = in_flight.progress_sink(i);
+            let scanner = Arc::clone(&worker_scanner);
+            thread::spawn(move || {
+                println!("[Worker {}] Starting up.", i);
+                loop {
+                    if sd.load(Ordering::SeqCst) {
+                        println!("[Worker {}] Shutdown flag set. Exiting.", i);
+                        break;
+                    }
+                    if paused.load(Ordering::SeqCst) {
+                        thread::sleep(Duration::from_millis(200));
+                        continue;
+                    }
+                    match scheduler.pop(Duration::from_millis(200)) {
+                        Some(job) => {
+                            let _busy = in_flight.start(i, &job);
+                            let path = job.path.clone();
+                            if handle_job(job, i, &retry, &tx_retry, &callbacks, &cancel, &progress) {
+                                // So the next rescan picks it up again.
+                                scanner.lock().unwrap().forget(&path);
+                            }
+                        }
+                        None if scheduler.is_closed() => {
+                            println!("[Worker {}] Queue closed. Shutting down.", i);
+                            break;
+                        }
+                        None => {}
+                    }
+                }
+            })
+        });
+        for profile in &profiles {
+            println!(
+                "[Main] Checking for existing archives in {}...",
+                profile.watch_path.display()
+            );
+        }
+        scan_folders(&profiles, &mut scanner.lock().unwrap(), &callbacks, &tx_jobs);
+        println!("[Main] Finished scanning for existing archives.");
+        #[cfg(unix)]
+        let control_thread = self.control.map(|(listener, path)| {
+            println!("[Main] Listening for commands on {}.", path.display());
+            let controls = Controls {
+                handler: handler.clone(),
+                scheduler: Arc::clone(&scheduler),
+                paused: Arc::clone(&paused),
+                in_flight: Arc::clone(&in_flight),
+            };
+            let sd = Arc::clone(&shutting_down);
+            thread::spawn(move || control::serve(listener, path, sd, move |request| controls.answer(request)))
+        });
+        let mut watched: Vec<Option<FolderId>> = profiles.iter().map(|p| folder_id(&p.watch_path)).collect();
+        drop(tx_retry);
+        let mut last_scan = Instant::now();
+        while !shutting_down.load(Ordering::SeqCst) {
+            // A folder that was removed, recreated or re-pointed silently
+            // stops delivering events; watch whatever is there now.
+            for ((profile, current), watcher) in profiles.iter().zip(&mut watched).zip(&mut watchers) {
+                let now = folder_id(&profile.watch_path);
+                if refresh_watch(profile, current, now, watcher, &handler) {
+                    rescan_requested.store(true, Ordering::SeqCst);
+                }
+            }
+            let periodic = self.rescan_interval.is_some_and(|interval| last_scan.elapsed() >= interval);
+            if rescan_requested.swap(false, Ordering::SeqCst) || periodic {
+                let queued = scan_folders(&profiles, &mut scanner.lock().unwrap(), &callbacks, &tx_jobs);
+                if queued > 0 {
+                    println!("[Main] Rescan found {} archive(s) that had been missed.", queued);
+                }
+                last_scan = Instant::now();
+            }
+            pool.supervise();
+            thread::park_timeout(std::time::Duration::from_millis(200));
+        }
+        #[cfg(unix)]
+        if let Some(control_thread) = control_thread {
+            let _ = control_thread.join();
+        }
+        drop(watchers);
+        drop(handler);
+        drop(tx_jobs);
+        scheduler.close();
+        let deadline = Instant::now() + self.shutdown_grace;
+        while pool.running() > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        let running = pool.running();
+        if running > 0 {
+            println!("[Main] Cancelling {} unfinished extraction(s).", running);
+            cancel.cancel();
+        }
+        pool.join();
+        println!("[Main] Shutdown complete.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::Outcome;
+    use std::io::Write;
+
+    #[test]
+    fn test_scanner_reports_new_archives() {
+        let td = std::env::temp_dir().join(format!("unzipper_scanner_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        fs::write(td.join("a.zip"), b"a").unwrap();
+        fs::write(td.join("notes.txt"), b"n").unwrap();
+        let mut scanner = Scanner::default();
+        assert_eq!(scanner.scan(&td), vec![td.join("a.zip")]);
+        assert!(scanner.scan(&td).is_empty());
+
+        fs::write(td.join("b.7z"), b"b").unwrap();
+        fs::write(td.join("c.zip"), b"c").unwrap();
+        scanner.note(&td.join("c.zip"));
+        assert_eq!(scanner.scan(&td), vec![td.join("b.7z")]);
+
+        // So does one whose job failed without moving it.
+        scanner.forget(&td.join("c.zip"));
+        assert_eq!(scanner.scan(&td), vec![td.join("c.zip")]);
+
+        // Gone and back again counts as new.
+        fs::remove_file(td.join("a.zip")).unwrap();
+        assert!(scanner.scan(&td).is_empty());
+        fs::write(td.join("a.zip"), b"a").unwrap();
+        assert_eq!(scanner.scan(&td), vec![td.join("a.zip")]);
+        fs::remove_dir_all(&td).ok();
+    }
+
+    fn handler_for(dir: &Path) -> (WatchHandler, mpsc::Receiver<Job>) {
+        let (tx_jobs, rx_jobs) = mpsc::channel();
+        let handler = WatchHandler {
+            profiles: vec![Arc::new(Profile {
+                watch_path: dir.to_path_buf(),
+                ..Profile::default()
+            })],
+            callbacks: Arc::new(Vec::new()),
+            tx_jobs,
+            scanner: Arc::new(Mutex::new(Scanner::default())),
+            rescan_requested: Arc::new(AtomicBool::new(false)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+        };
+        (handler, rx_jobs)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_retry_only_restores_quarantined_archives() {
+        let td = std::env::temp_dir().join(format!("unzipper_retry_test_{}", std::process::id()));
+        let quarantined = td.join(quarantine::DEFAULT_DIR);
+        fs::create_dir_all(&quarantined).unwrap();
+        let (handler, rx_jobs) = handler_for(&td);
+        let controls = Controls {
+            handler,
+            scheduler: Scheduler::new(8, 4, LargeArchives::default()),
+            paused: Arc::new(AtomicBool::new(false)),
+            in_flight: Arc::new(InFlight::default()),
+        };
+        let record = |path: &Path, original: &Path| {
+            let record = quarantine::QuarantineRecord {
+                original_path: original.to_path_buf(),
+                format: Some(String::from("zip")),
+                profile: String::new(),
+                error_kind: String::from("corrupt"),
+                error: String::new(),
+                quarantined_at: String::new(),
+            };
+            fs::write(path, b"zip").unwrap();
+            fs::write(format!("{}.json", path.display()), serde_json::to_string(&record).unwrap()).unwrap();
+        };
+
+        // A sidecar outside the quarantine folder must not move files around.
+        let outside = td.join("elsewhere.zip");
+        record(&outside, &td.join("moved.zip"));
+        let response = controls.answer(Request::Retry { path: outside.clone() });
+        assert!(matches!(response, Response::Error(_)));
+        assert!(outside.exists());
+        assert!(!td.join("moved.zip").exists());
+
+        record(&quarantined.join("a.zip"), &td.join("a.zip"));
+        let response = controls.answer(Request::Retry {
+            path: quarantined.join("a.zip"),
+        });
+        assert!(matches!(response, Response::Done(_)));
+        assert!(td.join("a.zip").exists());
+        assert_eq!(rx_jobs.try_recv().unwrap().path, td.join("a.zip"));
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_close_write_queues_archive() {
+        use notify::EventHandler;
+        let td = std::env::temp_dir().join(format!("unzipper_close_write_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        fs::write(td.join("a.zip"), b"a").unwrap();
+        let (mut handler, rx_jobs) = handler_for(&td);
+        let event = notify::Event::new(EventKind::Access(AccessKind::Close(AccessMode::Write))).add_path(td.join("a.zip"));
+        handler.handle_event(Ok(event));
+        assert_eq!(rx_jobs.try_recv().unwrap().path, td.join("a.zip"));
+        let event = notify::Event::new(EventKind::Access(AccessKind::Close(AccessMode::Read))).add_path(td.join("a.zip"));
+        handler.handle_event(Ok(event));
+        assert!(rx_jobs.try_recv().is_err());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_events_after_shutdown_are_dropped() {
+        use notify::EventHandler;
+        let td = std::env::temp_dir().join(format!("unzipper_shutdown_queue_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        fs::write(td.join("a.zip"), b"a").unwrap();
+        let (mut handler, rx_jobs) = handler_for(&td);
+        // The dispatcher is gone once the daemon shuts down.
+        drop(rx_jobs);
+        let event = notify::Event::new(EventKind::Create(notify::event::CreateKind::File)).add_path(td.join("a.zip"));
+        handler.handle_event(Ok(event));
+        let mut scanner = Scanner::default();
+        assert_eq!(scan_folders(&handler.profiles, &mut scanner, &[], &handler.tx_jobs), 0);
+        let controls = Controls {
+            handler,
+            scheduler: Scheduler::new(8, 4, LargeArchives::default()),
+            paused: Arc::new(AtomicBool::new(false)),
+            in_flight: Arc::new(InFlight::default()),
+        };
+        let response = controls.answer(Request::Enqueue { path: td.join("a.zip") });
+        assert_eq!(response, Response::Error(String::from("Daemon is shutting down")));
+        fs::remove_dir_all(&td).ok();
+    }
+
+    /// Runs `refresh_watch` for the folder at `path`, returning whether it
+    /// asked for a rescan and the names of the events it emitted.
+    fn refresh(
+        path: &Path,
+        current: &mut Option<FolderId>,
+        watcher: &mut Option<Box<dyn Watcher>>,
+    ) -> (bool, Vec<&'static str>) {
+        let (mut handler, _rx_jobs) = handler_for(path);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        handler.callbacks = Arc::new(vec![Box::new(move |event: &Event| {
+            sink.lock().unwrap().push(match event {
+                Event::WatchLost(_) => "lost",
+                Event::WatchRestored(_) => "restored",
+                _ => "other",
+            })
+        })]);
+        let profile = Arc::clone(&handler.profiles[0]);
+        let rescan = refresh_watch(&profile, current, folder_id(path), watcher, &handler);
+        let events = events.lock().unwrap().clone();
+        (rescan, events)
+    }
+
+    #[test]
+    fn test_refresh_watch_keeps_unchanged_folder() {
+        let td = std::env::temp_dir().join(format!("unzipper_refresh_same_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        let mut current = folder_id(&td);
+        let (handler, _rx_jobs) = handler_for(&td);
+        let mut watcher = Some(watcher_for(&handler.profiles[0], handler.clone()).unwrap());
+        let before = watcher.as_deref().map(|w| w as *const dyn Watcher as *const ());
+        assert_eq!(refresh(&td, &mut current, &mut watcher), (false, vec![]));
+        assert_eq!(watcher.as_deref().map(|w| w as *const dyn Watcher as *const ()), before);
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_refresh_watch_follows_recreated_folder() {
+        let td = std::env::temp_dir().join(format!("unzipper_refresh_recreate_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        let mut current = folder_id(&td);
+        let mut watcher = None;
+        fs::remove_dir(&td).unwrap();
+        assert_eq!(refresh(&td, &mut current, &mut watcher), (false, vec!["lost"]));
+        assert!(current.is_none() && watcher.is_none());
+        assert_eq!(refresh(&td, &mut current, &mut watcher), (false, vec![]));
+
+        fs::create_dir_all(&td).unwrap();
+        assert_eq!(refresh(&td, &mut current, &mut watcher), (true, vec!["restored"]));
+        assert_eq!(current, folder_id(&td));
+        assert!(watcher.is_some());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refresh_watch_follows_repointed_symlink() {
+        let td = std::env::temp_dir().join(format!("unzipper_refresh_symlink_test_{}", std::process::id()));
+        fs::create_dir_all(td.join("a")).unwrap();
+        fs::create_dir_all(td.join("b")).unwrap();
+        let link = td.join("link");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(td.join("a"), &link).unwrap();
+        let mut current = folder_id(&link);
+        let mut watcher = None;
+
+        fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(td.join("b"), &link).unwrap();
+        assert_eq!(refresh(&link, &mut current, &mut watcher), (true, vec!["lost", "restored"]));
+        assert_eq!(current.as_ref().map(|id| id.path.clone()), fs::canonicalize(td.join("b")).ok());
+        assert!(watcher.is_some());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_profile_for_resolves_watch_paths() {
+        let td = std::env::temp_dir().join(format!("unzipper_profile_for_test_{}", std::process::id()));
+        fs::create_dir_all(td.join("real")).unwrap();
+        let link = td.join("link");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(td.join("real"), &link).unwrap();
+        let profiles = vec![Arc::new(Profile {
+            watch_path: link.join("."),
+            ..Profile::default()
+        })];
+        assert!(profile_for(&profiles, &td.join("real/a.zip")).is_some());
+        assert!(profile_for(&profiles, &link.join("a.zip")).is_some());
+        assert!(profile_for(&profiles, &td.join("a.zip")).is_none());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_daemon_extracts_existing_archive() {
+        let td = std::env::temp_dir().join(format!(
+            "unzipper_daemon_test_{}_{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        fs::create_dir_all(&td).unwrap();
+        let mut z = zip::ZipWriter::new(fs::File::create(td.join("sample.zip")).unwrap());
+        z.start_file("inner.txt", zip::write::FileOptions::default()).unwrap();
+        z.write_all(b"hi").unwrap();
+        z.finish().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let daemon = Daemon::builder()
+            .watch(&td)
+            .workers(1)
+            .on_event(move |event| {
+                if let Event::Finished { result, .. } = event {
+                    let dir = match result {
+                        Ok(Report { outcome: Outcome::Extracted(dir), .. }) => Some(dir.clone()),
+                        _ => None,
+                    };
+                    tx.lock().unwrap().send(dir).unwrap();
+                }
+            })
+            .build()
+            .unwrap();
+        let handle = daemon.handle();
+        let runner = thread::spawn(move || daemon.run().unwrap());
+        let finished = rx.recv_timeout(std::time::Duration::from_secs(10)).unwrap();
+        handle.stop();
+        runner.join().unwrap();
+        assert_eq!(finished, Some(td.join("sample")));
+        assert_eq!(fs::read_to_string(td.join("sample").join("inner.txt")).unwrap(), "hi");
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_daemon_run_fails_before_extracting_anything() {
+        let td = std::env::temp_dir().join(format!("unzipper_daemon_watch_error_test_{}", std::process::id()));
+        fs::create_dir_all(td.join("a")).unwrap();
+        fs::create_dir_all(td.join("b")).unwrap();
+        fs::write(td.join("a/sample.zip"), b"not yet").unwrap();
+        let daemon = Daemon::builder().watch(td.join("a")).watch(td.join("b")).workers(1).build().unwrap();
+        fs::remove_dir(td.join("b")).unwrap();
+        assert!(daemon.run().is_err());
+        thread::sleep(Duration::from_millis(300));
+        assert!(td.join("a/sample.zip").exists());
+        assert!(!td.join("a/sample").exists());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_daemon_quarantines_broken_archive() {
+        let td = std::env::temp_dir().join(format!("unzipper_daemon_quarantine_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        fs::write(td.join("broken.zip"), b"not a zip").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let daemon = Daemon::builder()
+            .watch(&td)
+            .workers(1)
+            .on_event(move |event| {
+                if let Event::Quarantined { to, .. } = event {
+                    tx.lock().unwrap().send(to.clone()).unwrap();
+                }
+            })
+            .build()
+            .unwrap();
+        let handle = daemon.handle();
+        let runner = thread::spawn(move || daemon.run().unwrap());
+        let to = rx.recv_timeout(std::time::Duration::from_secs(10)).unwrap();
+        handle.stop();
+        runner.join().unwrap();
+        let dir = td.join(crate::quarantine::DEFAULT_DIR);
+        assert_eq!(to, dir.join("broken.zip"));
+        assert!(!td.join("broken.zip").exists());
+        assert!(dir.join("broken.zip.json").is_file());
+        fs::remove_dir_all(&td).ok();
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+use crate::extractors::collision::CollisionResolver;
+use crate::extractors::{checksum, EntryInfo, ExtractOptions};
+
+/// What to do when the destination folder for an archive already exists.
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExistingPolicy {
+    /// Extract into a fresh `name (N)` folder.
+    #[default]
+    NewFolder,
+    /// Extract into the existing folder, resolving files with the merge rule.
+    Merge,
+    /// Swap the existing folder for the freshly extracted one.
+    Replace,
+}
+
+/// Per-file rule applied when merging into an existing folder.
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeRule {
+    /// Extracted files replace existing ones.
+    #[default]
+    Overwrite,
+    /// Existing files are left untouched.
+    Skip,
+    /// The file with the newer modification time wins.
+    Newer,
+}
+
+/// Suffix appended to a destination folder name that is already taken.
+pub const DEFAULT_SUFFIX: &str = " ({n})";
+
+/// Renders `suffix` for the `n`th alternative, e.g. ` ({n})` or ` ({n:03})`.
+pub fn render_suffix(suffix: &str, n: usize) -> String {
+    let Some(open) = suffix.find("{n") else {
+        return format!("{}{}", suffix, n);
+    };
+    let Some(close) = suffix[open..].find('}').map(|i| open + i) else {
+        return format!("{}{}", suffix, n);
+    };
+    let width = suffix[open + 2..close]
+        .strip_prefix(':')
+        .and_then(|w| w.trim_start_matches('0').parse().ok())
+        .unwrap_or(0);
+    format!("{}{:0width$}{}", &suffix[..open], n, &suffix[close + 1..], width = width)
+}
+
+fn with_suffix(base: &Path, suffix: &str, n: usize) -> PathBuf {
+    let mut name = base.file_name().unwrap_or_default().to_os_string();
+    name.push(render_suffix(suffix, n));
+    base.with_file_name(name)
+}
+
+/// `base` itself, or the first numbered variant of it that doesn't exist yet.
+pub fn unique_dir(base: &Path, suffix: &str) -> PathBuf {
+    if !base.exists() {
+        return base.to_path_buf();
+    }
+    let mut counter = 1;
+    loop {
+        let candidate = with_suffix(base, suffix, counter);
+        if !candidate.exists() {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+/// Looks for an earlier extraction at `base` or one of its numbered variants
+/// whose files match `entries` exactly in path, size and CRC. Paths are the
+/// ones `options` resolve colliding entries to.
+pub fn find_identical_copy(
+    base: &Path,
+    suffix: &str,
+    entries: &[EntryInfo],
+    options: &ExtractOptions,
+) -> io::Result<Option<PathBuf>> {
+    let mut resolver = CollisionResolver::detached(options.collisions, options.case_insensitive);
+    let mut expected = HashMap::new();
+    for entry in entries.iter().filter(|e| !e.is_dir) {
+        if let Some(rel) = resolver.resolve(&entry.path)? {
+            expected.insert(rel, entry);
+        }
+    }
+    if expected.is_empty() {
+        return Ok(None);
+    }
+    let mut candidate = base.to_path_buf();
+    let mut counter = 1;
+    while candidate.is_dir() {
+        if matches_entries(&candidate, &expected)? {
+            return Ok(Some(candidate));
+        }
+        candidate = with_suffix(base, suffix, counter);
+        counter += 1;
+    }
+    Ok(None)
+}
+
+fn matches_entries(dir: &Path, expected: &HashMap<PathBuf, &EntryInfo>) -> io::Result<bool> {
+    let mut found = 0;
+    for entry in WalkDir::new(dir).min_depth(1) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(dir).expect("walkdir yields children of root");
+        let Some(info) = expected.get(rel) else {
+            return Ok(false);
+        };
+        if entry.metadata()?.len() != info.size {
+            return Ok(false);
+        }
+        if let Some(crc) = info.crc {
+            if checksum(fs::File::open(entry.path())?)?.0 != crc {
+                return Ok(false);
+            }
+        }
+        found += 1;
+    }
+    Ok(found == expected.len())
+}
+
+/// Hidden folder inside an existing destination that merge and replace
+/// extract into, one level below the watched folder.
+const STAGING_DIR: &str = ".unzipper-staging";
+
+/// `.{name}.unzipper-{what}` next to `dir`.
+fn sibling(dir: &Path, what: &str) -> PathBuf {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    dir.with_file_name(format!(".{}.unzipper-{}", name, what))
+}
+
+/// Where an archive gets extracted to. Merge and replace extract into a hidden
+/// staging folder first, so a failed extraction never touches the existing one.
+pub struct Destination {
+    dir: PathBuf,
+    staging: Option<PathBuf>,
+    /// Whether `dir` was created for this extraction, and so may be removed again.
+    created: bool,
+    policy: ExistingPolicy,
+    rule: MergeRule,
+}
+
+impl Destination {
+    pub fn prepare(base: &Path, suffix: &str, policy: ExistingPolicy, rule: MergeRule) -> io::Result<Self> {
+        let dir = Destination::plan(base, suffix, policy);
+        if policy == ExistingPolicy::Replace {
+            recover_replace(&dir)?;
+        }
+        let staging = if policy != ExistingPolicy::NewFolder && dir.exists() {
+            let staging = dir.join(STAGING_DIR);
+            if staging.exists() {
+                fs::remove_dir_all(&staging)?;
+            }
+            Some(staging)
+        } else {
+            None
+        };
+        let created = staging.is_none() && !dir.exists();
+        let dest = Destination {
+            dir,
+            staging,
+            created,
+            policy,
+            rule,
+        };
+        fs::create_dir_all(dest.work_dir())?;
+        Ok(dest)
+    }
+
+    /// The folder `prepare` would pick, without creating anything.
+    pub fn plan(base: &Path, suffix: &str, policy: ExistingPolicy) -> PathBuf {
+        match policy {
+            ExistingPolicy::NewFolder => unique_dir(base, suffix),
+            ExistingPolicy::Merge | ExistingPolicy::Replace => base.to_path_buf(),
+        }
+    }
+
+    /// The folder the extractor should write into.
+    pub fn work_dir(&self) -> &Path {
+        self.staging.as_deref().unwrap_or(&self.dir)
+    }
+
+    /// The folder the extracted files end up in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Moves staged files into place according to the policy.
+    pub fn commit(self) -> io::Result<PathBuf> {
+        let Some(staging) = &self.staging else {
+            return Ok(self.dir);
+        };
+        match self.policy {
+            ExistingPolicy::NewFolder => {}
+            ExistingPolicy::Merge => {
+                merge_tree(staging, &self.dir, self.rule)?;
+                fs::remove_dir_all(staging)?;
+            }
+            ExistingPolicy::Replace => {
+                // The staging folder can't be swapped with the folder it is
+                // in, so it moves next to it first.
+                let new = sibling(&self.dir, "new");
+                if new.exists() {
+                    fs::remove_dir_all(&new)?;
+                }
+                fs::rename(staging, &new)?;
+                if crate::platform::exchange(&new, &self.dir).is_ok() {
+                    fs::remove_dir_all(&new)?;
+                    return Ok(self.dir);
+                }
+                // Without an atomic swap the old folder is kept aside until
+                // the new one is in place; `recover_replace` puts it back
+                // after a crash in between.
+                let old = sibling(&self.dir, "old");
+                if old.exists() {
+                    fs::remove_dir_all(&old)?;
+                }
+                fs::rename(&self.dir, &old)?;
+                if let Err(e) = fs::rename(&new, &self.dir) {
+                    let _ = fs::rename(&old, &self.dir);
+                    let _ = fs::remove_dir_all(&new);
+                    return Err(e);
+                }
+                fs::remove_dir_all(&old)?;
+            }
+        }
+        Ok(self.dir)
+    }
+
+    /// Drops whatever a failed or cancelled extraction wrote.
+    pub fn discard(self) {
+        if let Some(staging) = &self.staging {
+            let _ = fs::remove_dir_all(staging);
+        } else if self.created {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// Cleans up after a replace that was interrupted: an old folder set aside
+/// goes back in place if the new one never got there.
+fn recover_replace(dir: &Path) -> io::Result<()> {
+    let old = sibling(dir, "old");
+    if old.exists() {
+        if fs::symlink_metadata(dir).is_err() {
+            fs::rename(&old, dir)?;
+        } else {
+            fs::remove_dir_all(&old)?;
+        }
+    }
+    let new = sibling(dir, "new");
+    if new.exists() {
+        fs::remove_dir_all(&new)?;
+    }
+    Ok(())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::symlink_metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Existing files below `dir` that merging `entries` with `rule` would replace.
+pub fn merge_overwrites(dir: &Path, rule: MergeRule, entries: &[EntryInfo]) -> Vec<PathBuf> {
+    entries
+        .iter()
+        .filter(|e| !e.is_dir)
+        .map(|e| (dir.join(&e.path), e))
+        .filter(|(target, e)| {
+            fs::symlink_metadata(target).is_ok()
+                && match rule {
+                    MergeRule::Overwrite => true,
+                    MergeRule::Skip => false,
+                    MergeRule::Newer => e.modified > modified(target),
+                }
+        })
+        .map(|(target, _)| target)
+        .collect()
+}
+
+fn merge_tree(from: &Path, to: &Path, rule: MergeRule) -> io::Result<()> {
+    let mut walker = WalkDir::new(from).min_depth(1).into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = entry?;
+        let rel = entry.path().strip_prefix(from).expect("walkdir yields children of root");
+        let target = to.join(rel);
+        let is_dir = entry.file_type().is_dir();
+        if let Ok(existing) = fs::symlink_metadata(&target) {
+            if is_dir && existing.is_dir() {
+                continue;
+            }
+            // A file against a file, or a file and a folder of the same name.
+            let replace = match rule {
+                MergeRule::Overwrite => true,
+                MergeRule::Skip => false,
+                MergeRule::Newer => modified(entry.path()) > modified(&target),
+            };
+            if !replace {
+                if is_dir {
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+            if existing.is_dir() {
+                fs::remove_dir_all(&target)?;
+            } else {
+                fs::remove_file(&target)?;
+            }
+        }
+        if is_dir {
+            fs::create_dir_all(&target)?;
+        } else {
+            fs::rename(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_suffix() {
+        assert_eq!(render_suffix(DEFAULT_SUFFIX, 1), " (1)");
+        assert_eq!(render_suffix(" ({n:03})", 1), " (001)");
+        assert_eq!(render_suffix("_{n:2}", 7), "_07");
+        assert_eq!(render_suffix("-", 3), "-3");
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let td = std::env::temp_dir().join(format!("unzipper_dest_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&td);
+        fs::create_dir_all(&td).unwrap();
+        td
+    }
+
+    #[test]
+    fn test_identical_copy_matches_resolved_names() {
+        let td = temp_dir("identical");
+        let dir = td.join("sample");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("README"), b"one").unwrap();
+        fs::write(dir.join("readme (2)"), b"two").unwrap();
+        let entry = |path: &str, data: &[u8]| EntryInfo {
+            path: PathBuf::from(path),
+            size: data.len() as u64,
+            compressed_size: None,
+            modified: None,
+            crc: Some(crc32fast::hash(data)),
+            is_dir: false,
+            encrypted: false,
+        };
+        let entries = [entry("README", b"one"), entry("readme", b"two")];
+        let options = ExtractOptions {
+            case_insensitive: true,
+            ..ExtractOptions::default()
+        };
+        assert_eq!(find_identical_copy(&dir, DEFAULT_SUFFIX, &entries, &options).unwrap(), Some(dir.clone()));
+        let options = ExtractOptions::default();
+        assert_eq!(find_identical_copy(&dir, DEFAULT_SUFFIX, &entries, &options).unwrap(), None);
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_merge_resolves_file_and_folder_conflicts() {
+        let td = temp_dir("merge");
+        let dir = td.join("out");
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("docs/a.txt"), b"old").unwrap();
+        fs::write(dir.join("notes"), b"old").unwrap();
+
+        let dest = Destination::prepare(&dir, DEFAULT_SUFFIX, ExistingPolicy::Merge, MergeRule::Overwrite).unwrap();
+        let work = dest.work_dir().to_path_buf();
+        fs::write(work.join("docs"), b"new").unwrap();
+        fs::create_dir_all(work.join("notes")).unwrap();
+        fs::write(work.join("notes/b.txt"), b"new").unwrap();
+        dest.commit().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("docs")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("notes/b.txt")).unwrap(), "new");
+        assert!(!dir.join(STAGING_DIR).exists());
+
+        let dest = Destination::prepare(&dir, DEFAULT_SUFFIX, ExistingPolicy::Merge, MergeRule::Skip).unwrap();
+        fs::create_dir_all(dest.work_dir().join("docs")).unwrap();
+        fs::write(dest.work_dir().join("docs/c.txt"), b"new").unwrap();
+        dest.commit().unwrap();
+        assert!(dir.join("docs").is_file());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_replace_swaps_folders_and_recovers() {
+        let td = temp_dir("replace");
+        let dir = td.join("out");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("stale.txt"), b"old").unwrap();
+        let dest = Destination::prepare(&dir, DEFAULT_SUFFIX, ExistingPolicy::Replace, MergeRule::Overwrite).unwrap();
+        assert!(dest.work_dir().starts_with(&dir));
+        fs::write(dest.work_dir().join("fresh.txt"), b"new").unwrap();
+        dest.commit().unwrap();
+        assert!(!dir.join("stale.txt").exists());
+        assert_eq!(fs::read_to_string(dir.join("fresh.txt")).unwrap(), "new");
+        let leftovers: Vec<_> = fs::read_dir(&td).unwrap().collect();
+        assert_eq!(leftovers.len(), 1);
+
+        // A crash after the old folder was set aside.
+        fs::rename(&dir, sibling(&dir, "old")).unwrap();
+        let dest = Destination::prepare(&dir, DEFAULT_SUFFIX, ExistingPolicy::Replace, MergeRule::Overwrite).unwrap();
+        dest.discard();
+        assert_eq!(fs::read_to_string(dir.join("fresh.txt")).unwrap(), "new");
+        assert!(!sibling(&dir, "old").exists());
+        fs::remove_dir_all(&td).ok();
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::profile::Profile;
+use crate::scheduler::{LargeSlot, Scheduler};
+
+/// Identity of an archive: its inode where available, so a file renamed
+/// while it is processed is still recognised, otherwise its path.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum ArchiveKey {
+    #[cfg(unix)]
+    Inode(u64, u64),
+    Path(PathBuf),
+}
+
+fn key_of(path: &Path) -> ArchiveKey {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(m) = fs::metadata(path) {
+            return ArchiveKey::Inode(m.dev(), m.ino());
+        }
+    }
+    ArchiveKey::Path(path.to_path_buf())
+}
+
+type InFlight = Arc<Mutex<HashSet<ArchiveKey>>>;
+
+/// Marks an archive as being processed until it is dropped.
+struct Claim {
+    key: ArchiveKey,
+    in_flight: InFlight,
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+pub(crate) struct Job {
+    pub path: PathBuf,
+    pub profile: Arc<Profile>,
+    /// Failed attempts so far.
+    pub failures: u32,
+    claim: Option<Claim>,
+    /// Set by the scheduler while a large archive is extracted.
+    pub slot: Option<LargeSlot>,
+}
+
+impl Job {
+    pub fn new(path: PathBuf, profile: Arc<Profile>) -> Self {
+        Job {
+            path,
+            profile,
+            failures: 0,
+            claim: None,
+            slot: None,
+        }
+    }
+
+    /// The same archive again, after one more failure.
+    pub fn retry(&self) -> Self {
+        Job {
+            failures: self.failures + 1,
+            ..Job::new(self.path.clone(), Arc::clone(&self.profile))
+        }
+    }
+}
+
+/// Spawns the thread between event sources and the scheduler. Jobs for the same
+/// path are coalesced until no new one arrived for `debounce`, and a job is
+/// dropped while another one for the same archive is still being processed.
+/// Returns the sender that event sources submit jobs to.
+pub(crate) fn spawn_dispatcher(
+    out: Arc<Scheduler>,
+    debounce: Duration,
+    shutting_down: Arc<AtomicBool>,
+) -> mpsc::Sender<Job> {
+    let (tx, rx) = mpsc::channel::<Job>();
+    let in_flight: InFlight = Arc::new(Mutex::new(HashSet::new()));
+    thread::spawn(move || {
+        let mut pending: HashMap<PathBuf, (Instant, Job)> = HashMap::new();
+        while !shutting_down.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            let timeout = pending
+                .values()
+                .map(|(due, _)| due.saturating_duration_since(now))
+                .min()
+                .unwrap_or(Duration::MAX)
+                .min(Duration::from_millis(200));
+            match rx.recv_timeout(timeout) {
+                Ok(mut job) => {
+                    if let Some((_, earlier)) = pending.remove(&job.path) {
+                        job.failures = job.failures.max(earlier.failures);
+                    }
+                    pending.insert(job.path.clone(), (Instant::now() + debounce, job));
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) if pending.is_empty() => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+            }
+            let now = Instant::now();
+            let due: Vec<PathBuf> = pending
+                .iter()
+                .filter(|(_, (due, _))| *due <= now)
+                .map(|(path, _)| path.clone())
+                .collect();
+            for path in due {
+                let (_, mut job) = pending.remove(&path).expect("collected from pending");
+                if !path.is_file() {
+                    continue;
+                }
+                let key = key_of(&path);
+                if !in_flight.lock().unwrap().insert(key.clone()) {
+                    println!(
+                        "[Main] {} is already being processed. Ignoring repeated event.",
+                        path.display()
+                    );
+                    continue;
+                }
+                job.claim = Some(Claim {
+                    key,
+                    in_flight: Arc::clone(&in_flight),
+                });
+                if out.push(job).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::LargeArchives;
+
+    #[test]
+    fn test_dispatcher_coalesces_and_locks() {
+        let td = std::env::temp_dir().join(format!("unzipper_dispatch_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        let archive = td.join("a.zip");
+        fs::write(&archive, b"zip").unwrap();
+        let profile = Arc::new(Profile::default());
+        let scheduler = Scheduler::new(8, 4, LargeArchives::default());
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let tx = spawn_dispatcher(Arc::clone(&scheduler), Duration::from_millis(50), Arc::clone(&shutting_down));
+        let wait = Duration::from_millis(500);
+
+        for _ in 0..3 {
+            tx.send(Job::new(archive.clone(), Arc::clone(&profile))).unwrap();
+        }
+        let job = scheduler.pop(wait).unwrap();
+        assert!(scheduler.pop(wait).is_none());
+
+        // Still in flight: a new event is ignored.
+        tx.send(Job::new(archive.clone(), Arc::clone(&profile))).unwrap();
+        assert!(scheduler.pop(wait).is_none());
+
+        drop(job);
+        tx.send(Job::new(archive.clone(), Arc::clone(&profile))).unwrap();
+        assert!(scheduler.pop(wait).is_some());
+        shutting_down.store(true, Ordering::SeqCst);
+        fs::remove_dir_all(&td).ok();
+    }
+}
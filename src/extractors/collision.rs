@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What to do when two entries of one archive map to the same output path.
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionPolicy {
+    /// Keep both, writing later entries as `name (2).ext`, `name (3).ext`, ...
+    #[default]
+    Rename,
+    /// Keep the first entry and skip later ones.
+    KeepFirst,
+    /// Let later entries overwrite earlier ones.
+    KeepLast,
+    /// Abort the extraction.
+    Fail,
+}
+
+/// Tracks the output paths claimed during a single extraction.
+pub struct CollisionResolver {
+    /// Folder checked for names taken before the extraction; `None` to
+    /// resolve without looking at the disk.
+    dest: Option<PathBuf>,
+    policy: CollisionPolicy,
+    case_insensitive: bool,
+    seen: HashMap<String, PathBuf>,
+}
+
+impl CollisionResolver {
+    pub fn new(dest: &Path, policy: CollisionPolicy, case_insensitive: bool) -> Self {
+        CollisionResolver {
+            dest: Some(dest.to_path_buf()),
+            policy,
+            case_insensitive,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Resolves names as an extraction into an empty folder would, e.g. to
+    /// tell where the entries of an archive were written.
+    pub fn detached(policy: CollisionPolicy, case_insensitive: bool) -> Self {
+        CollisionResolver {
+            dest: None,
+            policy,
+            case_insensitive,
+            seen: HashMap::new(),
+        }
+    }
+
+    fn key(&self, rel: &Path) -> String {
+        let key = rel.to_string_lossy().replace('\\', "/");
+        if self.case_insensitive {
+            key.to_lowercase()
+        } else {
+            key
+        }
+    }
+
+    /// Returns the relative path the entry should be written to, or `None`
+    /// if it has to be skipped.
+    pub fn resolve(&mut self, rel: &Path) -> io::Result<Option<PathBuf>> {
+        let key = self.key(rel);
+        let Some(first) = self.seen.get(&key) else {
+            self.seen.insert(key, rel.to_path_buf());
+            return Ok(Some(rel.to_path_buf()));
+        };
+        match self.policy {
+            CollisionPolicy::KeepFirst => Ok(None),
+            CollisionPolicy::KeepLast => Ok(Some(first.clone())),
+            CollisionPolicy::Fail => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Duplicate entry in archive: {}", rel.display()),
+            )),
+            CollisionPolicy::Rename => {
+                let renamed = self.next_free_name(rel);
+                let renamed_key = self.key(&renamed);
+                self.seen.insert(renamed_key, renamed.clone());
+                Ok(Some(renamed))
+            }
+        }
+    }
+
+    fn next_free_name(&self, rel: &Path) -> PathBuf {
+        let stem = rel.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+        let ext = rel.extension().and_then(|s| s.to_str());
+        let mut counter = 2;
+        loop {
+            let name = match ext {
+                Some(ext) => format!("{} ({}).{}", stem, counter, ext),
+                None => format!("{} ({})", stem, counter),
+            };
+            let candidate = rel.with_file_name(name);
+            let on_disk = self.dest.as_ref().is_some_and(|dest| dest.join(&candidate).exists());
+            if !self.seen.contains_key(&self.key(&candidate)) && !on_disk {
+                return candidate;
+            }
+            counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver(policy: CollisionPolicy, case_insensitive: bool) -> CollisionResolver {
+        CollisionResolver::new(Path::new("/nonexistent/unzipper"), policy, case_insensitive)
+    }
+
+    #[test]
+    fn test_rename_appends_counter() {
+        let mut r = resolver(CollisionPolicy::Rename, false);
+        let first = r.resolve(Path::new("dir/readme.txt")).unwrap();
+        let second = r.resolve(Path::new("dir/readme.txt")).unwrap();
+        let third = r.resolve(Path::new("dir/readme.txt")).unwrap();
+        assert_eq!(first, Some(PathBuf::from("dir/readme.txt")));
+        assert_eq!(second, Some(PathBuf::from("dir/readme (2).txt")));
+        assert_eq!(third, Some(PathBuf::from("dir/readme (3).txt")));
+    }
+
+    #[test]
+    fn test_case_insensitive_detection() {
+        let mut r = resolver(CollisionPolicy::KeepFirst, true);
+        assert!(r.resolve(Path::new("README")).unwrap().is_some());
+        assert!(r.resolve(Path::new("readme")).unwrap().is_none());
+
+        let mut r = resolver(CollisionPolicy::KeepFirst, false);
+        assert!(r.resolve(Path::new("README")).unwrap().is_some());
+        assert!(r.resolve(Path::new("readme")).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_keep_last_reuses_first_spelling() {
+        let mut r = resolver(CollisionPolicy::KeepLast, true);
+        r.resolve(Path::new("README")).unwrap();
+        assert_eq!(r.resolve(Path::new("readme")).unwrap(), Some(PathBuf::from("README")));
+    }
+
+    #[test]
+    fn test_fail_policy_errors() {
+        let mut r = resolver(CollisionPolicy::Fail, false);
+        r.resolve(Path::new("a.txt")).unwrap();
+        assert!(r.resolve(Path::new("a.txt")).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::io;
+
+use zip::result::ZipError;
+
+/// Why an extractor couldn't handle an archive.
+#[derive(Debug)]
+pub enum ExtractError {
+    /// The archive is damaged or truncated.
+    Corrupt(String),
+    /// Entries are encrypted and no password was given.
+    Encrypted,
+    /// The given password doesn't decrypt the archive.
+    WrongPassword,
+    /// The archive uses a format variant or compression method no extractor handles.
+    Unsupported(String),
+    /// The external program needed for the format isn't installed.
+    ToolMissing(&'static str),
+    /// The archive unpacks to more than the configured limit.
+    LimitExceeded,
+    /// Reading the archive or writing the destination failed.
+    Io(io::Error),
+    /// The extraction was stopped before it finished.
+    Cancelled,
+}
+
+/// I/O failures that tend to go away by themselves: a file still locked by
+/// the browser, a disk that was briefly full, a killed helper process.
+pub fn is_transient_io(e: &io::Error) -> bool {
+    // Windows sharing and lock violations.
+    if cfg!(windows) && matches!(e.raw_os_error(), Some(32) | Some(33)) {
+        return true;
+    }
+    matches!(
+        e.kind(),
+        io::ErrorKind::StorageFull
+            | io::ErrorKind::QuotaExceeded
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::PermissionDenied
+    )
+}
+
+impl ExtractError {
+    /// Whether trying again later may succeed. Everything but I/O trouble is
+    /// a property of the archive itself.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ExtractError::Io(e) => is_transient_io(e),
+            _ => false,
+        }
+    }
+
+    /// Whether the archive itself is to blame, as opposed to the tools or
+    /// the filesystem around it. Only such archives are worth quarantining.
+    pub fn is_archive_fault(&self) -> bool {
+        matches!(
+            self,
+            ExtractError::Corrupt(_)
+                | ExtractError::Encrypted
+                | ExtractError::WrongPassword
+                | ExtractError::Unsupported(_)
+                | ExtractError::LimitExceeded
+        )
+    }
+
+    /// Short machine-readable name of the variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ExtractError::Corrupt(_) => "corrupt",
+            ExtractError::Encrypted => "encrypted",
+            ExtractError::WrongPassword => "wrong-password",
+            ExtractError::Unsupported(_) => "unsupported",
+            ExtractError::ToolMissing(_) => "tool-missing",
+            ExtractError::LimitExceeded => "limit-exceeded",
+            ExtractError::Io(_) => "io",
+            ExtractError::Cancelled => "cancelled",
+        }
+    }
+
+    /// For errors raised while decoding an archive stream: anything the OS
+    /// didn't report is a problem with the data itself.
+    pub fn from_stream(e: io::Error) -> Self {
+        if e.raw_os_error().is_some() {
+            return ExtractError::Io(e);
+        }
+        match ExtractError::from(e) {
+            ExtractError::Io(e) => ExtractError::Corrupt(e.to_string()),
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractError::Corrupt(detail) => write!(f, "archive is damaged: {}", detail),
+            ExtractError::Encrypted => write!(f, "archive is encrypted and no password was given"),
+            ExtractError::WrongPassword => write!(f, "wrong password"),
+            ExtractError::Unsupported(detail) => write!(f, "unsupported archive: {}", detail),
+            ExtractError::ToolMissing(tool) => write!(f, "{} is not installed", tool),
+            ExtractError::LimitExceeded => write!(f, "unpacked size limit exceeded"),
+            ExtractError::Io(e) => write!(f, "{}", e),
+            ExtractError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExtractError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExtractError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::FileTooLarge => ExtractError::LimitExceeded,
+            // flate2 reports broken streams as InvalidInput. The OS uses it
+            // too, e.g. for names the destination filesystem can't store.
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof
+                if e.raw_os_error().is_none() =>
+            {
+                ExtractError::Corrupt(e.to_string())
+            }
+            _ => ExtractError::Io(e),
+        }
+    }
+}
+
+impl From<ZipError> for ExtractError {
+    fn from(e: ZipError) -> Self {
+        match e {
+            ZipError::Io(e) => ExtractError::from(e),
+            ZipError::InvalidArchive(detail) => ExtractError::Corrupt(detail.to_string()),
+            ZipError::UnsupportedArchive(detail) if detail == ZipError::PASSWORD_REQUIRED => ExtractError::Encrypted,
+            ZipError::UnsupportedArchive(detail) => ExtractError::Unsupported(detail.to_string()),
+            ZipError::FileNotFound => ExtractError::Corrupt(e.to_string()),
+        }
+    }
+}
+
+impl From<sevenz_rust::Error> for ExtractError {
+    fn from(e: sevenz_rust::Error) -> Self {
+        use sevenz_rust::Error;
+        match e {
+            Error::Io(e, _) | Error::FileOpen(e, _) => ExtractError::from(e),
+            Error::PasswordRequired => ExtractError::Encrypted,
+            Error::UnsupportedVersion { .. }
+            | Error::ExternalUnsupported
+            | Error::UnsupportedCompressionMethod(_)
+            | Error::MaxMemLimited { .. }
+            | Error::Unsupported(_) => ExtractError::Unsupported(e.to_string()),
+            _ => ExtractError::Corrupt(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_error_classification() {
+        let limit = io::Error::new(io::ErrorKind::FileTooLarge, "too big");
+        assert!(matches!(ExtractError::from(limit), ExtractError::LimitExceeded));
+        let crc = io::Error::new(io::ErrorKind::InvalidData, "Invalid checksum");
+        assert!(matches!(ExtractError::from(crc), ExtractError::Corrupt(_)));
+        let header = io::Error::other("archive header checksum mismatch");
+        assert!(matches!(ExtractError::from_stream(header), ExtractError::Corrupt(_)));
+        let stream = io::Error::new(io::ErrorKind::InvalidInput, "corrupt deflate stream");
+        assert!(matches!(ExtractError::from(stream), ExtractError::Corrupt(_)));
+        // EINVAL, e.g. a name vfat refuses.
+        let name = io::Error::from_raw_os_error(22);
+        assert!(matches!(ExtractError::from(name), ExtractError::Io(_)));
+        let disk = io::Error::from_raw_os_error(28);
+        assert!(matches!(ExtractError::from_stream(disk), ExtractError::Io(_)));
+        let password = ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED);
+        assert!(matches!(ExtractError::from(password), ExtractError::Encrypted));
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        assert!(ExtractError::from(io::Error::from(io::ErrorKind::StorageFull)).is_transient());
+        assert!(!ExtractError::from(io::Error::from(io::ErrorKind::NotFound)).is_transient());
+        assert!(!ExtractError::Corrupt(String::from("bad header")).is_transient());
+    }
+
+    #[test]
+    fn test_archive_faults() {
+        assert!(ExtractError::Corrupt(String::from("bad header")).is_archive_fault());
+        assert!(ExtractError::LimitExceeded.is_archive_fault());
+        assert!(!ExtractError::ToolMissing("7z").is_archive_fault());
+        assert!(!ExtractError::from(io::Error::from(io::ErrorKind::ReadOnlyFilesystem)).is_archive_fault());
+    }
+}
@@ -1,8 +1,256 @@
+use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{Duration, SystemTime};
+
+use crate::extractors::collision::{CollisionPolicy, CollisionResolver};
+pub use crate::extractors::error::ExtractError;
+pub use crate::extractors::progress::{Progress, ProgressSink, ProgressTracker};
+
+#[derive(Clone, Debug, Default)]
+pub struct ExtractOptions {
+    pub collisions: CollisionPolicy,
+    pub case_insensitive: bool,
+    /// Upper bound for the bytes written by one extraction, guarding against zip bombs.
+    pub max_unpacked_bytes: Option<u64>,
+    /// Checked between entries; once set the extraction fails with [`ExtractError::Cancelled`].
+    pub cancel: CancelToken,
+    pub progress: ProgressSink,
+}
+
+/// Asks running extractions to stop. Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Fails with [`ExtractError::Cancelled`] once [`cancel`](Self::cancel) was called.
+    pub fn check(&self) -> Result<(), ExtractError> {
+        if self.is_cancelled() {
+            return Err(ExtractError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+impl ExtractOptions {
+    pub fn resolver(&self, dest: &Path) -> CollisionResolver {
+        CollisionResolver::new(dest, self.collisions, self.case_insensitive)
+    }
+
+    pub fn size_limit(&self) -> SizeLimit {
+        SizeLimit { remaining: self.max_unpacked_bytes }
+    }
+
+    /// Logs the start of an entry, unless the progress display stands in for that.
+    pub fn log_entry(&self, worker_id: usize, entry_name: &str) {
+        if !self.progress.replaces_entry_log() {
+            log_extracting(worker_id, entry_name);
+        }
+    }
+}
+
+/// Counts down the bytes an extraction may still write.
+pub struct SizeLimit {
+    remaining: Option<u64>,
+}
+
+impl SizeLimit {
+    fn exceeded() -> io::Error {
+        io::Error::new(io::ErrorKind::FileTooLarge, "Unpacked size limit exceeded")
+    }
+
+    /// Reserves `bytes` up front, for formats whose entry sizes can be trusted.
+    pub fn charge(&mut self, bytes: u64) -> io::Result<()> {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.checked_sub(bytes).ok_or_else(Self::exceeded)?;
+        }
+        Ok(())
+    }
+
+    /// Copies `reader` to `writer`, failing as soon as the limit is crossed.
+    pub fn copy<R: io::Read + ?Sized, W: io::Write>(&mut self, reader: &mut R, writer: &mut W) -> io::Result<u64> {
+        let Some(remaining) = self.remaining else {
+            return io::copy(reader, writer);
+        };
+        let copied = io::copy(&mut io::Read::take(reader, remaining + 1), writer)?;
+        self.charge(copied)?;
+        Ok(copied)
+    }
+
+    /// Checks the sizes an archive declares before extracting it.
+    pub fn check_declared(&self, entries: &[EntryInfo]) -> io::Result<()> {
+        match self.remaining {
+            Some(remaining) if entries.iter().map(|e| e.size).sum::<u64>() > remaining => Err(Self::exceeded()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A file or directory stored in an archive, as reported without extracting it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryInfo {
+    pub path: PathBuf,
+    pub size: u64,
+    pub compressed_size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    pub crc: Option<u32>,
+    pub is_dir: bool,
+    pub encrypted: bool,
+}
+
+/// Why an archive entry was not written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// An earlier entry already claimed the output path.
+    Duplicate,
+    /// The entry name points outside the destination.
+    UnsafePath,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::Duplicate => write!(f, "duplicate entry"),
+            SkipReason::UnsafePath => write!(f, "path outside the destination"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedEntry {
+    /// Entry name as stored in the archive.
+    pub name: String,
+    pub reason: SkipReason,
+}
+
+/// What a single extraction did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExtractReport {
+    /// Format label as returned by [`for_path`].
+    pub format: String,
+    /// Files written to the destination; directories are not counted.
+    pub entries_written: usize,
+    /// Size of the archive file.
+    pub bytes_in: u64,
+    /// Bytes written to the destination.
+    pub bytes_out: u64,
+    pub skipped: Vec<SkippedEntry>,
+    /// Problems that didn't stop the extraction, e.g. timestamps that couldn't be set.
+    pub warnings: Vec<String>,
+    pub elapsed: Duration,
+}
+
+impl ExtractReport {
+    pub fn new(format: &str, archive: &Path) -> Self {
+        ExtractReport {
+            format: format.to_string(),
+            bytes_in: fs::metadata(archive).map(|m| m.len()).unwrap_or(0),
+            ..ExtractReport::default()
+        }
+    }
+
+    pub fn written(&mut self, bytes: u64) {
+        self.entries_written += 1;
+        self.bytes_out += bytes;
+    }
+
+    pub fn skip(&mut self, name: &str, reason: SkipReason) {
+        self.skipped.push(SkippedEntry {
+            name: name.to_string(),
+            reason,
+        });
+    }
+
+    pub fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    /// Adds what extracting an archive found inside this one did.
+    pub fn absorb(&mut self, nested: ExtractReport) {
+        self.entries_written += nested.entries_written;
+        self.bytes_out += nested.bytes_out;
+        self.skipped.extend(nested.skipped);
+        self.warnings.extend(nested.warnings);
+    }
+}
 
 pub trait ArchiveExtractor {
-    fn extract(&self, path: &Path, dest: &Path, worker_id: usize) -> io::Result<()>;
+    fn extract(&self, path: &Path, dest: &Path, worker_id: usize, options: &ExtractOptions) -> Result<ExtractReport, ExtractError>;
+    fn entries(&self, path: &Path) -> Result<Vec<EntryInfo>, ExtractError>;
+    /// Decompresses every entry without writing it, verifying checksums.
+    /// Returns the number of entries checked.
+    fn test(&self, path: &Path) -> Result<usize, ExtractError>;
+}
+
+/// Picks the extractor for an archive based on its extension, together with
+/// the name of the detected format.
+pub fn for_path(path: &Path) -> Option<(String, Box<dyn ArchiveExtractor>)> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|s| s.to_ascii_lowercase())?;
+    let (format, extractor): (&str, Box<dyn ArchiveExtractor>) = match ext.as_str() {
+        "zip" => ("zip", Box::new(zip::ZipExtractor)),
+        "tar" => ("tar", Box::new(targz::TarGzExtractor)),
+        "tgz" | "taz" => ("tar.gz", Box::new(targz::TarGzExtractor)),
+        "gz" if path.to_string_lossy().to_ascii_lowercase().ends_with(".tar.gz") => {
+            ("tar.gz", Box::new(targz::TarGzExtractor))
+        }
+        "gz" => ("gz", Box::new(targz::TarGzExtractor)),
+        "7z" => ("7z", Box::new(sevenz::SevenZExtractor)),
+        "rar" => ("rar", Box::new(rar::RarExtractor)),
+        _ => return None,
+    };
+    Some((format.to_string(), extractor))
+}
+
+/// CRC32 and length of everything `reader` yields.
+pub fn checksum<R: io::Read>(mut reader: R) -> io::Result<(u32, u64)> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut len = 0u64;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok((hasher.finalize(), len));
+        }
+        hasher.update(&buf[..n]);
+        len += n as u64;
+    }
+}
+
+/// Normalizes an entry name into a relative path that stays inside the
+/// destination, or `None` if it would escape it.
+pub fn enclosed_path(name: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(c) => out.push(c),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if out.as_os_str().is_empty() {
+        None
+    } else {
+        Some(out)
+    }
 }
 
 #[inline]
@@ -10,6 +258,16 @@ pub fn log_extracting(worker_id: usize, entry_name: &str) {
     println!("[Worker {}] Extracting: {}", worker_id, entry_name);
 }
 
+#[inline]
+pub fn log_skipped_duplicate(worker_id: usize, entry_name: &str) {
+    println!("[Worker {}] Skipping duplicate entry: {}", worker_id, entry_name);
+}
+
+#[inline]
+pub fn log_skipped_unsafe(worker_id: usize, entry_name: &str) {
+    println!("[Worker {}] Skipping entry outside the destination: {}", worker_id, entry_name);
+}
+
 #[inline]
 pub fn log_start(worker_id: usize, src: &Path, dest: &Path, _kind: &str) {
     println!("[Worker {}] Unzipping file: {} to {}", worker_id, src.display(), dest.display());
@@ -30,6 +288,9 @@ pub fn log_error_launch(worker_id: usize, tool: &str, error: &dyn std::fmt::Disp
     eprintln!("[Worker {}] {} not available or failed to launch: {}", worker_id, tool, error);
 }
 
+pub mod collision;
+pub mod error;
+pub mod progress;
 pub mod zip;
 pub mod targz;
 pub mod sevenz;
@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How far an extraction has got. Entries are files; directories aren't
+/// counted. Totals are `None` for formats that only tell them at the end.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Unpacked bytes of the entries written or skipped so far.
+    pub bytes_done: u64,
+    pub bytes_total: Option<u64>,
+    pub entries_done: usize,
+    pub entries_total: Option<usize>,
+    /// Set on the last update of an extraction, whether it succeeded or not.
+    pub finished: bool,
+}
+
+impl Progress {
+    /// Share of the bytes done, if the total is known.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.bytes_total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.bytes_done as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+}
+
+type Callback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Receives [`Progress`] updates from extractions, a few times a second at
+/// most. Clones share the callback; the default drops every update.
+#[derive(Clone, Default)]
+pub struct ProgressSink {
+    callback: Option<Callback>,
+    replaces_entry_log: bool,
+}
+
+impl ProgressSink {
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        ProgressSink {
+            callback: Some(Arc::new(callback)),
+            replaces_entry_log: false,
+        }
+    }
+
+    /// Asks extractors to leave out their line per entry, e.g. because the
+    /// callback draws a progress bar on the same terminal.
+    pub fn replacing_entry_log(mut self) -> Self {
+        self.replaces_entry_log = true;
+        self
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.callback.is_some()
+    }
+
+    pub fn replaces_entry_log(&self) -> bool {
+        self.replaces_entry_log
+    }
+
+    fn report(&self, progress: &Progress) {
+        if let Some(callback) = &self.callback {
+            callback(progress);
+        }
+    }
+}
+
+impl std::fmt::Debug for ProgressSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProgressSink")
+            .field("active", &self.is_active())
+            .field("replaces_entry_log", &self.replaces_entry_log)
+            .finish()
+    }
+}
+
+/// Time between two updates passed to a [`ProgressSink`].
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counts what one extraction has done and passes it on to a
+/// [`ProgressSink`]. Sends a last update with `finished` set when dropped.
+pub struct ProgressTracker {
+    sink: ProgressSink,
+    progress: Progress,
+    last_report: Instant,
+}
+
+impl ProgressTracker {
+    pub fn new(sink: &ProgressSink, bytes_total: Option<u64>, entries_total: Option<usize>) -> Self {
+        let tracker = ProgressTracker {
+            sink: sink.clone(),
+            progress: Progress {
+                bytes_total,
+                entries_total,
+                ..Progress::default()
+            },
+            last_report: Instant::now(),
+        };
+        tracker.sink.report(&tracker.progress);
+        tracker
+    }
+
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.progress.bytes_done += bytes;
+        self.report_if_due();
+    }
+
+    pub fn entry_done(&mut self) {
+        self.progress.entries_done += 1;
+        self.report_if_due();
+    }
+
+    /// Replaces the counts, for tools that report totals so far themselves.
+    pub fn set(&mut self, bytes_done: u64, entries_done: usize) {
+        self.progress.bytes_done = bytes_done;
+        self.progress.entries_done = entries_done;
+        self.report_if_due();
+    }
+
+    /// Wraps `reader` so the bytes read through it count as done.
+    pub fn reader<R: io::Read>(&mut self, reader: R) -> ProgressReader<'_, R> {
+        ProgressReader { inner: reader, tracker: self }
+    }
+
+    fn report_if_due(&mut self) {
+        if self.sink.is_active() && self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.sink.report(&self.progress);
+            self.last_report = Instant::now();
+        }
+    }
+}
+
+impl Drop for ProgressTracker {
+    fn drop(&mut self) {
+        self.progress.finished = true;
+        self.sink.report(&self.progress);
+    }
+}
+
+/// A reader that counts its bytes towards a [`ProgressTracker`].
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    tracker: &'a mut ProgressTracker,
+}
+
+impl<R: io::Read> io::Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.tracker.add_bytes(n as u64);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_tracker_reports_and_finishes() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let updates = Arc::clone(&updates);
+            ProgressSink::new(move |p| updates.lock().unwrap().push(*p))
+        };
+        {
+            let mut tracker = ProgressTracker::new(&sink, Some(10), Some(2));
+            let mut buf = Vec::new();
+            tracker.reader(&b"hello"[..]).read_to_end(&mut buf).unwrap();
+            tracker.entry_done();
+            std::thread::sleep(REPORT_INTERVAL);
+            tracker.add_bytes(5);
+            tracker.entry_done();
+        }
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.first().unwrap().bytes_done, 0);
+        assert!(updates.len() >= 3);
+        let last = updates.last().unwrap();
+        assert!(last.finished);
+        assert_eq!((last.bytes_done, last.entries_done), (10, 2));
+        assert_eq!(last.fraction(), Some(1.0));
+    }
+}
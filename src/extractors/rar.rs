@@ -1,33 +1,472 @@
-use std::io;
-use std::path::Path;
-use std::process::Command;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::extractors::{ArchiveExtractor, log_start, log_done, log_error_status, log_error_launch};
+use crate::extractors::collision::CollisionPolicy;
+use crate::extractors::{ArchiveExtractor, CancelToken, EntryInfo, ExtractError, ExtractOptions, ExtractReport, ProgressTracker, SkipReason, enclosed_path, log_skipped_duplicate, log_start, log_done, log_error_status, log_error_launch};
 
 pub struct RarExtractor;
 
+/// Splits `7z l -slt` output into one key/value map per entry. The block
+/// describing the archive itself carries a `Type` key and is dropped.
+fn parse_slt(text: &str) -> Vec<HashMap<String, String>> {
+    let mut blocks = Vec::new();
+    let mut current = HashMap::new();
+    for line in text.lines().chain(std::iter::once("")) {
+        if let Some((key, value)) = line.split_once(" = ") {
+            current.insert(key.trim().to_string(), value.to_string());
+        } else if line.trim().is_empty() && !current.is_empty() {
+            let block = std::mem::take(&mut current);
+            if block.contains_key("Path") && !block.contains_key("Type") {
+                blocks.push(block);
+            }
+        }
+    }
+    blocks
+}
+
+/// Parses the local `Modified` timestamp of `7z l -slt`, which may carry
+/// fractional seconds.
+fn parse_modified(value: &str) -> Option<SystemTime> {
+    let value = value.split('.').next()?;
+    let naive = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").ok()?;
+    let local = naive.and_local_timezone(chrono::Local).earliest()?;
+    Some(local.into())
+}
+
+fn launch_error(e: io::Error) -> ExtractError {
+    match e.kind() {
+        io::ErrorKind::NotFound => ExtractError::ToolMissing("7z"),
+        _ => ExtractError::Io(e),
+    }
+}
+
+/// Runs 7z, telling a missing binary apart from other launch failures.
+fn run_7z(command: &mut Command) -> Result<Output, ExtractError> {
+    command.output().map_err(launch_error)
+}
+
+/// Picks the progress 7z prints with `-bsp1` out of its output, e.g.
+/// ` 45% 12 - photos/a.jpg`, redrawn in place with backspaces.
+#[derive(Default)]
+struct ProgressParser {
+    pending: String,
+}
+
+impl ProgressParser {
+    /// The latest percentage and, if given, number of files done in `chunk`.
+    fn feed(&mut self, chunk: &[u8]) -> Option<(u64, Option<usize>)> {
+        self.pending.push_str(&String::from_utf8_lossy(chunk));
+        let mut latest = None;
+        let end = self.pending.rfind(['\u{8}', '\r', '\n']).map_or(0, |i| i + 1);
+        for segment in self.pending[..end].split(['\u{8}', '\r', '\n']) {
+            let Some((percent, rest)) = segment.trim_start().split_once('%') else {
+                continue;
+            };
+            let Ok(percent) = percent.parse::<u64>() else {
+                continue;
+            };
+            let files = rest.split_whitespace().next().and_then(|n| n.parse().ok());
+            latest = Some((percent.min(100), files));
+        }
+        self.pending.drain(..end);
+        latest
+    }
+}
+
+/// Like [`run_7z`], but kills 7z as soon as `cancel` is set. Every chunk
+/// 7z writes to stdout is also passed to `on_stdout`.
+fn run_7z_cancellable(
+    command: &mut Command,
+    cancel: &CancelToken,
+    mut on_stdout: impl FnMut(&[u8]),
+) -> Result<Output, ExtractError> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(launch_error)?;
+    // Drained on their own threads so a chatty 7z never blocks on a full
+    // pipe; stdout is passed on in chunks as it comes.
+    let (tx_chunks, rx_chunks) = mpsc::channel::<Vec<u8>>();
+    let stdout = child.stdout.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n @ 1..) = pipe.read(&mut buf) {
+                if tx_chunks.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        })
+    });
+    let stderr = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            buf
+        })
+    });
+    let mut stdout_buf = Vec::new();
+    let mut take_chunks = |stdout_buf: &mut Vec<u8>| {
+        for chunk in rx_chunks.try_iter() {
+            on_stdout(&chunk);
+            stdout_buf.extend_from_slice(&chunk);
+        }
+    };
+    let status = loop {
+        take_chunks(&mut stdout_buf);
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancel.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(ExtractError::Cancelled);
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    if let Some(stdout) = stdout {
+        let _ = stdout.join();
+    }
+    take_chunks(&mut stdout_buf);
+    Ok(Output {
+        status,
+        stdout: stdout_buf,
+        stderr: stderr.and_then(|t| t.join().ok()).unwrap_or_default(),
+    })
+}
+
+/// Classifies a failed 7z run by its messages. No password is ever passed,
+/// so a password complaint means the archive is encrypted. Only what 7z
+/// writes to stderr counts, without the archive's path and the entry names
+/// it appends after ` : `, so names like `encrypted_notes.txt` don't decide.
+fn classify_failure(out: &Output, archive: &Path) -> ExtractError {
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let archive = archive.display().to_string();
+    let messages: Vec<&str> = stderr
+        .lines()
+        .map(|l| l.split(" : ").next().unwrap_or_default())
+        .map(|l| l.trim_start_matches("ERROR:").trim())
+        .filter(|l| !l.is_empty() && *l != archive)
+        .collect();
+    let lower = messages.join("\n").to_lowercase();
+    let detail = stderr
+        .lines()
+        .find(|l| l.contains("ERROR"))
+        .unwrap_or_else(|| stderr.trim())
+        .trim()
+        .to_string();
+    if out.status.code().is_none() {
+        ExtractError::Io(io::Error::new(io::ErrorKind::Interrupted, format!("7z was killed ({})", out.status)))
+    } else if lower.contains("wrong password") || lower.contains("encrypted") {
+        ExtractError::Encrypted
+    } else if lower.contains("unsupported method") || lower.contains("unsupported feature") {
+        ExtractError::Unsupported(detail)
+    } else if ["data error", "crc failed", "unexpected end", "headers error", "open the file as archive"]
+        .iter()
+        .any(|m| lower.contains(m))
+    {
+        ExtractError::Corrupt(detail)
+    } else {
+        ExtractError::Io(io::Error::other(format!("7z failed ({}): {}", out.status, detail)))
+    }
+}
+
+/// Lists the entries of an archive via `7z l -slt`.
+fn list_entries(path: &Path) -> Result<Vec<EntryInfo>, ExtractError> {
+    let out = run_7z(Command::new("7z").arg("l").arg("-slt").arg("-ba").arg(path))?;
+    if !out.status.success() {
+        return Err(classify_failure(&out, path));
+    }
+    let entries = parse_slt(&String::from_utf8_lossy(&out.stdout))
+        .into_iter()
+        .filter_map(|block| {
+            let is_dir = block.get("Folder").is_some_and(|f| f == "+")
+                || block.get("Attributes").is_some_and(|a| a.starts_with('D'));
+            Some(EntryInfo {
+                path: enclosed_path(Path::new(block.get("Path")?))?,
+                size: block.get("Size").and_then(|s| s.parse().ok()).unwrap_or(0),
+                compressed_size: block.get("Packed Size").and_then(|s| s.parse().ok()),
+                modified: block.get("Modified").and_then(|m| parse_modified(m)),
+                crc: block.get("CRC").and_then(|c| u32::from_str_radix(c, 16).ok()),
+                is_dir,
+                encrypted: block.get("Encrypted").is_some_and(|e| e == "+"),
+            })
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Fills `report` from the summary `7z x` prints once it is done. Archives
+/// with a single file get a `Size` line but no `Files` line.
+fn read_summary(stdout: &str, report: &mut ExtractReport) {
+    let mut files = None;
+    for line in stdout.lines() {
+        // Leftovers of the progress display end in backspaces.
+        let line = line.rsplit('\u{8}').next().unwrap_or(line);
+        if let Some(value) = line.strip_prefix("Files:") {
+            files = value.trim().parse().ok();
+        } else if let Some(value) = line.strip_prefix("Size:") {
+            report.bytes_out = value.trim().parse().unwrap_or(0);
+            files = files.or(Some(1));
+        } else if line.starts_with("WARNING") {
+            report.warn(line.trim().to_string());
+        }
+    }
+    report.entries_written = files.unwrap_or(0);
+}
+
+/// Folder inside the destination that 7z extracts into first, so entries
+/// can be moved to the names the collision policy picks.
+const STAGING_DIR: &str = ".unzipper-rar";
+
+/// The name 7z's `-aou` gives a file when `taken` already holds its path:
+/// `name_1.ext`, `name_2.ext`, ... split at the last dot like 7-Zip does.
+fn auto_renamed(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    if !taken.contains(path) {
+        return path.to_path_buf();
+    }
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => name.split_at(i),
+        None => (name.as_str(), ""),
+    };
+    (1..)
+        .map(|n| path.with_file_name(format!("{}_{}{}", base, n, ext)))
+        .find(|candidate| !taken.contains(candidate))
+        .expect("a free name")
+}
+
+/// Moves what 7z wrote into `staging` to the names `options` resolve for
+/// each entry, in archive order.
+fn place_entries(
+    entries: &[EntryInfo],
+    staging: &Path,
+    dest: &Path,
+    worker_id: usize,
+    options: &ExtractOptions,
+    report: &mut ExtractReport,
+) -> Result<(), ExtractError> {
+    let mut resolver = options.resolver(dest);
+    let mut staged = HashSet::new();
+    for entry in entries {
+        if entry.is_dir {
+            fs::create_dir_all(dest.join(&entry.path))?;
+            continue;
+        }
+        let source = staging.join(auto_renamed(&entry.path, &staged));
+        staged.insert(source.strip_prefix(staging).unwrap_or(&source).to_path_buf());
+        if fs::symlink_metadata(&source).is_err() {
+            report.warn(format!("7z did not write {}", entry.path.display()));
+            continue;
+        }
+        let name = entry.path.to_string_lossy();
+        let Some(rel) = resolver.resolve(&entry.path)? else {
+            log_skipped_duplicate(worker_id, &name);
+            report.skip(&name, SkipReason::Duplicate);
+            report.entries_written = report.entries_written.saturating_sub(1);
+            report.bytes_out = report.bytes_out.saturating_sub(entry.size);
+            continue;
+        };
+        let target = dest.join(rel);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&source, &target)?;
+    }
+    Ok(())
+}
+
 impl ArchiveExtractor for RarExtractor {
-    fn extract(&self, path: &Path, dest: &Path, worker_id: usize) -> io::Result<()> {
+    fn extract(&self, path: &Path, dest: &Path, worker_id: usize, options: &ExtractOptions) -> Result<ExtractReport, ExtractError> {
+        let started = Instant::now();
         log_start(worker_id, path, dest, "rar");
-        let output = Command::new("7z")
-            .arg("x")
-            .arg("-y")
-            .arg(format!("-o{}", dest.display()))
-            .arg(path)
-            .output();
-        match output {
-            Ok(out) if out.status.success() => {
-                log_done(worker_id, path, "rar");
-                Ok(())
+        // 7z can't name duplicates the way the other extractors do, so the
+        // listing decides where each entry ends up.
+        let entries = list_entries(path)?;
+        options.size_limit().check_declared(&entries)?;
+        if options.collisions == CollisionPolicy::Fail {
+            let mut resolver = options.resolver(dest);
+            for entry in entries.iter().filter(|e| !e.is_dir) {
+                resolver.resolve(&entry.path)?;
             }
-            Ok(out) => {
-                log_error_status(worker_id, path, "7z", &out.status);
-                Err(io::Error::new(io::ErrorKind::Other, "7z extraction failed"))
+        }
+        let files = entries.iter().filter(|e| !e.is_dir);
+        let totals = (Some(files.clone().map(|e| e.size).sum::<u64>()), Some(files.count()));
+        let staging = dest.join(STAGING_DIR);
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        let mut progress = ProgressTracker::new(&options.progress, totals.0, totals.1);
+        let mut parser = ProgressParser::default();
+        let out = run_7z_cancellable(
+            Command::new("7z")
+                .arg("x")
+                .arg("-y")
+                .arg("-bsp1")
+                .arg("-aou")
+                .arg(format!("-o{}", staging.display()))
+                .arg(path),
+            &options.cancel,
+            |chunk| {
+                if let Some((percent, files)) = parser.feed(chunk) {
+                    let bytes = totals.0.map_or(0, |total| total * percent / 100);
+                    progress.set(bytes, files.unwrap_or(0));
+                }
+            },
+        )
+        .inspect_err(|e| {
+            if !matches!(e, ExtractError::Cancelled) {
+                log_error_launch(worker_id, "7z", e)
             }
-            Err(e) => {
-                log_error_launch(worker_id, "7z", &e);
-                Err(e)
+        });
+        let mut report = ExtractReport::new("rar", path);
+        let placed = out.and_then(|out| {
+            if !out.status.success() {
+                log_error_status(worker_id, path, "7z", &out.status);
+                return Err(classify_failure(&out, path));
             }
+            read_summary(&String::from_utf8_lossy(&out.stdout), &mut report);
+            place_entries(&entries, &staging, dest, worker_id, options, &mut report)
+        });
+        let _ = fs::remove_dir_all(&staging);
+        placed?;
+        drop(progress);
+        log_done(worker_id, path, "rar");
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    fn entries(&self, path: &Path) -> Result<Vec<EntryInfo>, ExtractError> {
+        list_entries(path)
+    }
+
+    fn test(&self, path: &Path) -> Result<usize, ExtractError> {
+        let out = run_7z(Command::new("7z").arg("t").arg(path))?;
+        if !out.status.success() {
+            return Err(classify_failure(&out, path));
         }
+        Ok(list_entries(path)?.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_slt_skips_archive_block() {
+        let text = "Path = bundle.rar\nType = Rar5\nPhysical Size = 120\n\n\
+                    Path = docs\nFolder = +\nSize = 0\n\n\
+                    Path = docs/readme.txt\nFolder = -\nSize = 5\nCRC = 3610A686\n";
+        let blocks = parse_slt(text);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1]["Path"], "docs/readme.txt");
+        assert_eq!(blocks[1]["CRC"], "3610A686");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_classify_failure() {
+        use std::os::unix::process::ExitStatusExt;
+        let out = |stderr: &str| Output {
+            status: std::process::ExitStatus::from_raw(2 << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        let archive = Path::new("/downloads/encrypted stuff.rar");
+        assert!(matches!(
+            classify_failure(&out("ERROR: Data Error in encrypted file. Wrong password? : a.txt"), archive),
+            ExtractError::Encrypted
+        ));
+        assert!(matches!(
+            classify_failure(
+                &out("ERROR: /downloads/encrypted stuff.rar\n/downloads/encrypted stuff.rar\nCan not open the file as archive"),
+                archive
+            ),
+            ExtractError::Corrupt(_)
+        ));
+        assert!(matches!(classify_failure(&out("ERROR: disk full"), archive), ExtractError::Io(_)));
+
+        // Entry names show up in the progress on stdout and after error messages.
+        let mut failed = out("ERROR: Data Error : docs/encrypted_notes.txt");
+        failed.stdout = b"- docs/encrypted_notes.txt\n- wrong password.txt\n".to_vec();
+        assert!(matches!(classify_failure(&failed, archive), ExtractError::Corrupt(_)));
+    }
+
+    #[test]
+    fn test_read_summary() {
+        let mut report = ExtractReport::default();
+        read_summary("Everything is Ok\n\nFolders: 1\nFiles: 2\nSize:       1234\nCompressed: 567\n", &mut report);
+        assert_eq!((report.entries_written, report.bytes_out), (2, 1234));
+
+        let mut report = ExtractReport::default();
+        read_summary("Everything is Ok\n\nSize:       5\nCompressed: 120\n", &mut report);
+        assert_eq!((report.entries_written, report.bytes_out), (1, 5));
+
+        let mut report = ExtractReport::default();
+        read_summary("\u{8}\u{8}\u{8}    \u{8}\u{8}\u{8}Files: 3\nSize:       9\n", &mut report);
+        assert_eq!((report.entries_written, report.bytes_out), (3, 9));
+    }
+
+    #[test]
+    fn test_auto_renamed_follows_7z() {
+        let mut taken = HashSet::new();
+        let mut next = |name: &str| {
+            let path = auto_renamed(Path::new(name), &taken);
+            taken.insert(path.clone());
+            path
+        };
+        assert_eq!(next("docs/a.txt"), PathBuf::from("docs/a.txt"));
+        assert_eq!(next("docs/a.txt"), PathBuf::from("docs/a_1.txt"));
+        assert_eq!(next("docs/a_2.txt"), PathBuf::from("docs/a_2.txt"));
+        assert_eq!(next("docs/a.txt"), PathBuf::from("docs/a_3.txt"));
+        assert_eq!(next("Makefile"), PathBuf::from("Makefile"));
+        assert_eq!(next("Makefile"), PathBuf::from("Makefile_1"));
+    }
+
+    #[test]
+    fn test_place_entries_resolves_names() {
+        let td = std::env::temp_dir().join(format!("unzipper_rar_place_test_{}", std::process::id()));
+        let staging = td.join(STAGING_DIR);
+        fs::create_dir_all(staging.join("docs")).unwrap();
+        for (name, data) in [("docs/a.txt", "1"), ("docs/a_1.txt", "2"), ("docs/A.TXT", "3")] {
+            fs::write(staging.join(name), data).unwrap();
+        }
+        let entry = |path: &str, is_dir: bool| EntryInfo {
+            path: PathBuf::from(path),
+            size: 1,
+            compressed_size: None,
+            modified: None,
+            crc: None,
+            is_dir,
+            encrypted: false,
+        };
+        let entries = [entry("docs", true), entry("docs/a.txt", false), entry("docs/a.txt", false), entry("docs/A.TXT", false)];
+        let options = ExtractOptions {
+            case_insensitive: true,
+            ..ExtractOptions::default()
+        };
+        let mut report = ExtractReport::default();
+        place_entries(&entries, &staging, &td, 0, &options, &mut report).unwrap();
+        assert_eq!(fs::read_to_string(td.join("docs/a.txt")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(td.join("docs/a (2).txt")).unwrap(), "2");
+        assert_eq!(fs::read_to_string(td.join("docs/A (3).TXT")).unwrap(), "3");
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_progress_parser() {
+        let mut parser = ProgressParser::default();
+        assert_eq!(parser.feed(b"Extracting archive: a.rar\n  0%"), None);
+        assert_eq!(parser.feed(b"\x08\x08\x08\x08 12% 3 - docs/a.tx"), Some((0, None)));
+        assert_eq!(parser.feed(b"t\x08\x08\x08\x08"), Some((12, Some(3))));
+        assert_eq!(parser.feed(b"100% 8\x08\x08\x08\nEverything is Ok\n"), Some((100, Some(8))));
     }
 }
@@ -1,46 +1,123 @@
 use std::fs;
 use std::io;
 use std::path::Path;
-use crate::extractors::{ArchiveExtractor, log_extracting, log_start, log_done};
+use std::time::Instant;
+use crate::extractors::{ArchiveExtractor, EntryInfo, ExtractError, ExtractOptions, ExtractReport, ProgressTracker, SkipReason, enclosed_path, log_skipped_duplicate, log_skipped_unsafe, log_start, log_done};
 
 pub struct SevenZExtractor;
 
 impl ArchiveExtractor for SevenZExtractor {
-    fn extract(&self, path: &Path, dest: &Path, worker_id: usize) -> io::Result<()> {
-        crate::wait_until_stable(path, 5, std::time::Duration::from_millis(300))?;
+    fn extract(&self, path: &Path, dest: &Path, worker_id: usize, options: &ExtractOptions) -> Result<ExtractReport, ExtractError> {
+        let started = Instant::now();
+        let mut report = ExtractReport::new("7z", path);
         log_start(worker_id, path, dest, "7z");
         {
             let mut sz = sevenz_rust::SevenZReader::open(
                 path,
                 sevenz_rust::Password::empty(),
-            )
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-            sz.for_each_entries(|entry, mut reader| {
+            )?;
+            let mut resolver = options.resolver(dest);
+            let mut limit = options.size_limit();
+            let files = sz.archive().files.iter().filter(|f| !f.is_directory());
+            let (bytes_total, entries_total) = files.fold((0, 0), |(bytes, count), f| (bytes + f.size(), count + 1));
+            let mut progress = ProgressTracker::new(&options.progress, Some(bytes_total), Some(entries_total));
+            sz.for_each_entries(|entry, reader| {
+                if options.cancel.is_cancelled() {
+                    return Ok(false);
+                }
                 let name = entry.name();
-                let out = dest.join(name);
-                if !out.starts_with(dest) { return Err(io::Error::new(io::ErrorKind::Other, "Invalid entry path").into()); }
+                let Some(rel) = enclosed_path(Path::new(name)) else {
+                    log_skipped_unsafe(worker_id, name);
+                    report.skip(name, SkipReason::UnsafePath);
+                    io::copy(&mut progress.reader(reader), &mut io::sink())?;
+                    if !entry.is_directory() {
+                        progress.entry_done();
+                    }
+                    return Ok(true);
+                };
                 if entry.is_directory() {
-                    let _ = fs::create_dir_all(&out);
+                    let _ = fs::create_dir_all(dest.join(&rel));
                     return Ok(true);
                 }
-                log_extracting(worker_id, name);
+                let Some(rel) = resolver.resolve(&rel)? else {
+                    log_skipped_duplicate(worker_id, name);
+                    report.skip(name, SkipReason::Duplicate);
+                    io::copy(&mut progress.reader(reader), &mut io::sink())?;
+                    progress.entry_done();
+                    return Ok(true);
+                };
+                options.log_entry(worker_id, name);
+                let out = dest.join(rel);
                 if let Some(p) = out.parent() { let _ = fs::create_dir_all(p); }
                 if out.exists() {
-                    if let Ok(perms) = fs::metadata(&out).and_then(|m| Ok(m.permissions())) {
+                    if let Ok(perms) = fs::metadata(&out).map(|m| m.permissions()) {
                         if perms.readonly() {
                             let mut p = perms;
+                            #[allow(clippy::permissions_set_readonly_false)]
                             p.set_readonly(false);
                             let _ = fs::set_permissions(&out, p);
                         }
                     }
                 }
                 let mut f = fs::OpenOptions::new().write(true).create(true).truncate(true).open(&out)?;
-                io::copy(&mut reader, &mut f)?;
+                report.written(limit.copy(&mut progress.reader(reader), &mut f)?);
+                progress.entry_done();
+                if entry.has_last_modified_date {
+                    if let Err(e) = f.set_modified(entry.last_modified_date().into()) {
+                        report.warn(format!("Could not set modification time of {}: {}", out.display(), e));
+                    }
+                }
                 Ok(true)
-            })
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            })?;
+            options.cancel.check()?;
         }
         log_done(worker_id, path, "7z");
-        Ok(())
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    fn entries(&self, path: &Path) -> Result<Vec<EntryInfo>, ExtractError> {
+        let sz = sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty())?;
+        let archive = sz.archive();
+        let encrypted_folders: Vec<bool> = archive
+            .folders
+            .iter()
+            .map(|folder| {
+                folder
+                    .coders
+                    .iter()
+                    .any(|c| c.decompression_method_id() == sevenz_rust::SevenZMethod::ID_AES256SHA256)
+            })
+            .collect();
+        Ok(archive
+            .files
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| {
+                let folder = archive.stream_map.file_folder_index[i];
+                Some(EntryInfo {
+                    path: enclosed_path(Path::new(entry.name()))?,
+                    size: entry.size(),
+                    compressed_size: (entry.compressed_size > 0).then_some(entry.compressed_size),
+                    modified: entry
+                        .has_last_modified_date
+                        .then(|| entry.last_modified_date().into()),
+                    crc: entry.has_crc.then_some(entry.crc as u32),
+                    is_dir: entry.is_directory(),
+                    encrypted: folder.is_some_and(|f| encrypted_folders[f]),
+                })
+            })
+            .collect())
+    }
+
+    fn test(&self, path: &Path) -> Result<usize, ExtractError> {
+        let mut sz = sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty())?;
+        let mut count = 0;
+        sz.for_each_entries(|_, reader| {
+            io::copy(reader, &mut io::sink())?;
+            count += 1;
+            Ok(true)
+        })?;
+        Ok(count)
     }
 }
@@ -2,33 +2,235 @@ use flate2::read::GzDecoder;
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use tar::Archive as TarArchive;
-use crate::extractors::{ArchiveExtractor, log_start, log_done};
+use crate::extractors::{ArchiveExtractor, EntryInfo, ExtractError, ExtractOptions, ExtractReport, ProgressTracker, SkipReason, checksum, enclosed_path, log_skipped_duplicate, log_skipped_unsafe, log_start, log_done};
 
 pub struct TarGzExtractor;
 
+fn unpack_tar<R: io::Read>(
+    mut tar: TarArchive<R>,
+    dest: &Path,
+    worker_id: usize,
+    options: &ExtractOptions,
+    report: &mut ExtractReport,
+) -> Result<(), ExtractError> {
+    let mut resolver = options.resolver(dest);
+    let mut limit = options.size_limit();
+    // A tar stream only tells its size once it has been read to the end.
+    let mut progress = ProgressTracker::new(&options.progress, None, None);
+    for entry in tar.entries().map_err(ExtractError::from_stream)? {
+        options.cancel.check()?;
+        let mut entry = entry.map_err(ExtractError::from_stream)?;
+        let name = entry.path().map_err(ExtractError::from_stream)?.to_string_lossy().into_owned();
+        if !entry.header().entry_type().is_file() {
+            if !safe_link(&entry)? || !entry.unpack_in(dest).map_err(ExtractError::from_stream)? {
+                log_skipped_unsafe(worker_id, &name);
+                report.skip(&name, SkipReason::UnsafePath);
+            }
+            continue;
+        }
+        let Some(rel) = enclosed_path(Path::new(&name)) else {
+            log_skipped_unsafe(worker_id, &name);
+            report.skip(&name, SkipReason::UnsafePath);
+            progress.entry_done();
+            continue;
+        };
+        let Some(rel) = resolver.resolve(&rel)? else {
+            log_skipped_duplicate(worker_id, &name);
+            report.skip(&name, SkipReason::Duplicate);
+            progress.entry_done();
+            continue;
+        };
+        options.log_entry(worker_id, &name);
+        let size = entry.size();
+        limit.charge(size)?;
+        let out = dest.join(rel);
+        if !stays_inside(dest, &out)? {
+            log_skipped_unsafe(worker_id, &name);
+            report.skip(&name, SkipReason::UnsafePath);
+            progress.entry_done();
+            continue;
+        }
+        if let Some(p) = out.parent() { fs::create_dir_all(p)?; }
+        entry.unpack(&out).map_err(ExtractError::from_stream)?;
+        report.written(size);
+        progress.add_bytes(size);
+        progress.entry_done();
+    }
+    Ok(())
+}
+
+/// Symlinks may only point to a relative path without `..`, so that nothing
+/// written through them can land outside the destination.
+fn safe_link<R: io::Read>(entry: &tar::Entry<R>) -> Result<bool, ExtractError> {
+    if !entry.header().entry_type().is_symlink() {
+        return Ok(true);
+    }
+    let target = entry.link_name().map_err(ExtractError::from_stream)?;
+    Ok(target.is_some_and(|t| enclosed_path(&t).is_some()))
+}
+
+/// Checks that the folder `out` goes into resolves to a place inside `dest`,
+/// even when an earlier entry or an existing file made part of it a symlink.
+fn stays_inside(dest: &Path, out: &Path) -> io::Result<bool> {
+    let dest = fs::canonicalize(dest)?;
+    let Some(existing) = out.parent().and_then(|p| p.ancestors().find(|a| a.exists())) else {
+        return Ok(false);
+    };
+    Ok(fs::canonicalize(existing)?.starts_with(&dest))
+}
+
+fn list_tar<R: io::Read>(tar: TarArchive<R>) -> Result<Vec<EntryInfo>, ExtractError> {
+    read_tar(tar).map_err(ExtractError::from_stream)
+}
+
+fn read_tar<R: io::Read>(mut tar: TarArchive<R>) -> io::Result<Vec<EntryInfo>> {
+    let mut entries = Vec::new();
+    for entry in tar.entries()? {
+        let entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+        let Some(rel) = enclosed_path(&entry.path()?) else { continue };
+        let size = entry.size();
+        let modified = entry.header().mtime().ok().map(|t| UNIX_EPOCH + Duration::from_secs(t));
+        let is_dir = entry_type.is_dir();
+        let crc = if is_dir { None } else { Some(checksum(entry)?.0) };
+        entries.push(EntryInfo {
+            path: rel,
+            size,
+            compressed_size: None,
+            modified,
+            crc,
+            is_dir,
+            encrypted: false,
+        });
+    }
+    Ok(entries)
+}
+
+fn is_tarball(file_name: &str) -> bool {
+    file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") || file_name.ends_with(".taz")
+}
+
+/// Format label matching [`crate::extractors::for_path`].
+fn format_of(file_name: &str) -> &'static str {
+    if is_tarball(file_name) {
+        "tar.gz"
+    } else if file_name.ends_with(".tar") {
+        "tar"
+    } else {
+        "gz"
+    }
+}
+
 impl ArchiveExtractor for TarGzExtractor {
-    fn extract(&self, path: &Path, dest: &Path, worker_id: usize) -> io::Result<()> {
+    fn extract(&self, path: &Path, dest: &Path, worker_id: usize, options: &ExtractOptions) -> Result<ExtractReport, ExtractError> {
+        let started = Instant::now();
         let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+        let mut report = ExtractReport::new(format_of(file_name), path);
         log_start(worker_id, path, dest, "tar/gz");
         {
             let file = fs::File::open(path)?;
-            if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") || file_name.ends_with(".taz") {
+            if is_tarball(file_name) {
                 let gz = GzDecoder::new(file);
-                let mut tar = TarArchive::new(gz);
-                tar.unpack(dest)?;
+                unpack_tar(TarArchive::new(gz), dest, worker_id, options, &mut report)?;
             } else if file_name.ends_with(".tar") {
-                let mut tar = TarArchive::new(file);
-                tar.unpack(dest)?;
+                unpack_tar(TarArchive::new(file), dest, worker_id, options, &mut report)?;
             } else if file_name.ends_with(".gz") {
                 let mut gz = GzDecoder::new(file);
                 let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
                 let out_file_path = dest.join(stem);
                 let mut out = fs::File::create(out_file_path)?;
-                io::copy(&mut gz, &mut out)?;
+                let mut progress = ProgressTracker::new(&options.progress, None, Some(1));
+                let written = options
+                    .size_limit()
+                    .copy(&mut progress.reader(&mut gz), &mut out)
+                    .map_err(ExtractError::from_stream)?;
+                report.written(written);
+                progress.entry_done();
             }
         }
         log_done(worker_id, path, "tar/gz");
-        Ok(())
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    fn entries(&self, path: &Path) -> Result<Vec<EntryInfo>, ExtractError> {
+        let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+        let file = fs::File::open(path)?;
+        if is_tarball(file_name) {
+            list_tar(TarArchive::new(GzDecoder::new(file)))
+        } else if file_name.ends_with(".tar") {
+            list_tar(TarArchive::new(file))
+        } else if file_name.ends_with(".gz") {
+            let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            let compressed_size = file.metadata()?.len();
+            let (crc, size) = checksum(GzDecoder::new(file)).map_err(ExtractError::from_stream)?;
+            Ok(vec![EntryInfo {
+                path: stem.into(),
+                size,
+                compressed_size: Some(compressed_size),
+                modified: None,
+                crc: Some(crc),
+                is_dir: false,
+                encrypted: false,
+            }])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn test(&self, path: &Path) -> Result<usize, ExtractError> {
+        // Listing already reads every byte: tar headers are checksummed and
+        // the gzip trailer CRC is verified once the stream ends.
+        Ok(self.entries(path)?.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_cannot_redirect_later_entries() {
+        let td = std::env::temp_dir().join(format!("unzipper_tar_symlink_test_{}", std::process::id()));
+        let outside = td.join("outside");
+        let dest = td.join("out");
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        // Left behind in a folder the archive is merged into.
+        std::os::unix::fs::symlink(&outside, dest.join("existing")).unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, "evil", &outside).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, "up", "../outside").unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        builder.append_data(&mut header, "evil/x", &b"x"[..]).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        builder.append_data(&mut header, "existing/y", &b"y"[..]).unwrap();
+        let archive = td.join("evil.tar");
+        fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+
+        let report = TarGzExtractor.extract(&archive, &dest, 0, &ExtractOptions::default()).unwrap();
+        assert!(!outside.join("x").exists());
+        assert!(!outside.join("y").exists());
+        assert!(!fs::symlink_metadata(dest.join("evil")).unwrap().file_type().is_symlink());
+        assert!(fs::symlink_metadata(dest.join("up")).is_err());
+        assert_eq!(report.skipped.len(), 3);
+        assert_eq!(report.entries_written, 1);
+        assert_eq!(fs::read(dest.join("evil/x")).unwrap(), b"x");
+        fs::remove_dir_all(&td).ok();
     }
 }
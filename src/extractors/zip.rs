@@ -1,34 +1,106 @@
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::time::Instant;
+use zip::result::ZipError;
 use zip::ZipArchive;
-use crate::extractors::ArchiveExtractor;
+use crate::extractors::{ArchiveExtractor, EntryInfo, ExtractError, ExtractOptions, ExtractReport, ProgressTracker, SkipReason, log_skipped_duplicate, log_skipped_unsafe};
 
 pub struct ZipExtractor;
 
 impl ArchiveExtractor for ZipExtractor {
-    fn extract(&self, path: &Path, dest: &Path, worker_id: usize) -> io::Result<()> {
+    fn extract(&self, path: &Path, dest: &Path, worker_id: usize, options: &ExtractOptions) -> Result<ExtractReport, ExtractError> {
+        let started = Instant::now();
+        let mut report = ExtractReport::new("zip", path);
         println!("[Worker {}] Unzipping file: {} to {}", worker_id, path.display(), dest.display());
         {
             let file = fs::File::open(path)?;
             let mut archive = ZipArchive::new(file)?;
+            let mut resolver = options.resolver(dest);
+            let mut limit = options.size_limit();
+            let (mut bytes_total, mut entries_total) = (0, 0);
             for i in 0..archive.len() {
+                let file = archive.by_index_raw(i)?;
+                if !file.is_dir() {
+                    bytes_total += file.size();
+                    entries_total += 1;
+                }
+            }
+            let mut progress = ProgressTracker::new(&options.progress, Some(bytes_total), Some(entries_total));
+            for i in 0..archive.len() {
+                options.cancel.check()?;
                 let mut file = archive.by_index(i)?;
-                println!("[Worker {}] Extracting: {}", worker_id, file.name());
-                let outpath = match file.enclosed_name() {
-                    Some(path) => dest.join(path),
-                    None => continue
+                options.log_entry(worker_id, file.name());
+                let rel = match file.enclosed_name() {
+                    Some(path) => path.to_path_buf(),
+                    None => {
+                        log_skipped_unsafe(worker_id, file.name());
+                        report.skip(file.name(), SkipReason::UnsafePath);
+                        if !file.is_dir() {
+                            progress.add_bytes(file.size());
+                            progress.entry_done();
+                        }
+                        continue;
+                    }
                 };
                 if file.name().ends_with('/') {
-                    fs::create_dir_all(&outpath)?;
+                    fs::create_dir_all(dest.join(&rel))?;
                     continue;
                 }
+                let Some(rel) = resolver.resolve(&rel)? else {
+                    log_skipped_duplicate(worker_id, file.name());
+                    report.skip(file.name(), SkipReason::Duplicate);
+                    progress.add_bytes(file.size());
+                    progress.entry_done();
+                    continue;
+                };
+                let outpath = dest.join(rel);
                 if let Some(p) = outpath.parent() { if !p.exists() { fs::create_dir_all(p)?; } }
                 let mut outfile = fs::File::create(&outpath)?;
-                io::copy(&mut file, &mut outfile)?;
+                let written = limit.copy(&mut progress.reader(&mut file), &mut outfile).map_err(ExtractError::from_stream)?;
+                report.written(written);
+                progress.entry_done();
+                if let Ok(mtime) = file.last_modified().to_time() {
+                    if let Err(e) = outfile.set_modified(mtime.into()) {
+                        report.warn(format!("Could not set modification time of {}: {}", outpath.display(), e));
+                    }
+                }
             }
         }
         println!("[Worker {}] Successfully unzipped {}", worker_id, path.display());
-        Ok(())
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    fn entries(&self, path: &Path) -> Result<Vec<EntryInfo>, ExtractError> {
+        let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+        let mut entries = Vec::with_capacity(archive.len());
+        for i in 0..archive.len() {
+            let encrypted = matches!(
+                archive.by_index(i),
+                Err(ZipError::UnsupportedArchive(msg)) if msg == ZipError::PASSWORD_REQUIRED
+            );
+            let file = archive.by_index_raw(i)?;
+            let Some(rel) = file.enclosed_name() else { continue };
+            entries.push(EntryInfo {
+                path: rel.to_path_buf(),
+                size: file.size(),
+                compressed_size: Some(file.compressed_size()),
+                modified: file.last_modified().to_time().ok().map(Into::into),
+                crc: Some(file.crc32()),
+                is_dir: file.is_dir(),
+                encrypted,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn test(&self, path: &Path) -> Result<usize, ExtractError> {
+        let mut archive = ZipArchive::new(fs::File::open(path)?)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            io::copy(&mut file, &mut io::sink()).map_err(ExtractError::from_stream)?;
+        }
+        Ok(archive.len())
     }
 }
@@ -0,0 +1,27 @@
+//! Watches folders for downloaded archives and extracts them.
+//!
+//! [`Daemon`] runs the watcher, [`extract_archive`] handles a single file the
+//! same way, and new formats plug in through [`ArchiveExtractor`].
+
+pub mod audit;
+pub mod control;
+pub mod daemon;
+pub mod dest;
+mod dispatch;
+pub mod extractors;
+mod nested;
+pub mod partial;
+pub mod platform;
+pub mod process;
+pub mod profile;
+pub mod quarantine;
+pub mod retry;
+mod scheduler;
+pub mod template;
+
+pub use daemon::{Daemon, DaemonBuilder, DaemonHandle, Event};
+pub use extractors::{ArchiveExtractor, EntryInfo, ExtractError, ExtractOptions, ExtractReport, Progress, ProgressSink, SkipReason, SkippedEntry};
+pub use process::{extract_archive, extract_archive_with_progress, inspect, Outcome, ProcessError, Report};
+pub use profile::Profile;
+pub use retry::RetryPolicy;
+pub use scheduler::{default_workers, LargeArchives};
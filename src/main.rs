@@ -1,440 +1,512 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use fs2::FileExt;
-use notify::{EventKind, RecursiveMode, Result, Watcher};
 use std::fs;
-use std::io;
-use std::path::{Path, PathBuf};
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    mpsc, Arc, Mutex,
-};
-use std::thread;
-use walkdir::WalkDir;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::Duration;
 
-mod extractors;
-mod platform;
+use unzipper::dest::{ExistingPolicy, MergeRule, DEFAULT_SUFFIX};
+use unzipper::extractors::collision::CollisionPolicy;
+use unzipper::partial::PartialPreset;
+use unzipper::profile::WatchBackend;
+use unzipper::{
+    extract_archive_with_progress, inspect, platform, profile, Daemon, EntryInfo, LargeArchives, Outcome, ProcessError, Profile, Progress,
+    ProgressSink, RetryPolicy,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(short = 'p', long)]
     watch_path: Option<PathBuf>,
+    /// TOML file with one `[[profile]]` table per watched folder; replaces the options below
+    #[arg(short = 'c', long, conflicts_with = "watch_path")]
+    config: Option<PathBuf>,
+    /// How often the daemon tries an archive that fails for a transient reason (locked file, full disk)
+    #[arg(long, value_name = "ATTEMPTS", default_value_t = RetryPolicy::default().max_attempts)]
+    retries: u32,
+    /// Seconds before the first retry; doubled for every further one
+    #[arg(long, value_name = "SECS", default_value_t = RetryPolicy::default().initial_delay.as_secs())]
+    retry_delay: u64,
+    /// Archives extracted in parallel [default: one per CPU core, between 2 and 8]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(usize))]
+    workers: Option<usize>,
+    /// Seconds to let running extractions finish on shutdown before cancelling them
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    shutdown_grace: u64,
+    /// Archives of at least this many megabytes count as large
+    #[arg(long, value_name = "MB", default_value_t = LargeArchives::default().threshold >> 20)]
+    large_size: u64,
+    /// How many large archives may be extracted at the same time
+    #[arg(long, value_name = "N", default_value_t = LargeArchives::default().max_concurrent)]
+    max_large: usize,
+    /// Also look through the watched folders every SECS seconds, in case an event was missed
+    #[arg(long, value_name = "SECS")]
+    rescan_interval: Option<u64>,
+    /// Where to listen for `unzipper ctl` [default: $XDG_RUNTIME_DIR/unzipper.sock; none for a dry run]
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+    /// Append what happens to every archive to FILE, one JSON object per line
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+    #[command(flatten)]
+    options: ProfileArgs,
 }
 
-fn is_processable_archive_extension(ext: &str) -> bool {
-    matches!(
-        ext.to_ascii_lowercase().as_str(),
-        "zip" | "rar" | "7z" | "tar" | "gz"
-    )
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Extract the given archives once, without watching anything
+    Extract(Box<ExtractArgs>),
+    /// List the entries of archives without extracting them
+    List(InspectArgs),
+    /// Verify the checksums of every entry of archives without extracting them
+    Test(InspectArgs),
+    /// Talk to the running daemon through its control socket
+    Ctl(CtlArgs),
 }
 
-fn is_processable_path(path: &Path) -> bool {
-    path.is_file()
-        && path
-        .extension()
-        .and_then(|e| e.to_str())
-        .map_or(false, is_processable_archive_extension)
+#[derive(clap::Args, Debug)]
+struct CtlArgs {
+    /// Socket of the daemon [default: $XDG_RUNTIME_DIR/unzipper.sock]
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+    #[command(subcommand)]
+    command: CtlCommand,
 }
 
-fn delete_file(path: &Path, worker_id: usize) {
-    println!(
-        "[Worker {}] Trying to delete file: {}",
-        worker_id,
-        path.display()
-    );
-    let _ = fs::remove_file(path);
+#[derive(Subcommand, Debug)]
+enum CtlCommand {
+    /// Show whether the daemon is paused, what is queued and what the workers are doing
+    Status,
+    /// Start no further extractions until `resume`; running ones finish
+    Pause,
+    /// Undo `pause`
+    Resume,
+    /// Queue an archive in a watched folder
+    Enqueue { path: PathBuf },
+    /// Move a quarantined archive back to where it was found and queue it
+    Retry { path: PathBuf },
+    /// Look through the watched folders for archives that were missed
+    Rescan,
+    /// Shut the daemon down like Ctrl+C does
+    Stop,
 }
 
-pub(crate) fn wait_until_stable(
-    path: &Path,
-    attempts: usize,
-    delay: std::time::Duration,
-) -> io::Result<()> {
-    let mut prev_len = None;
-    for _ in 0..attempts {
-        match fs::metadata(path) {
-            Ok(m) => {
-                let len = m.len();
-                if let Some(p) = prev_len {
-                    if p == len {
-                        return Ok(());
-                    }
-                }
-                prev_len = Some(len);
-            }
-            Err(e) => {
-                let _ = e.kind();
-            }
-        }
-        std::thread::sleep(delay);
-    }
-    Ok(())
+#[derive(clap::Args, Debug)]
+struct InspectArgs {
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
 }
 
-fn find_unique_dest_dir(path: &Path) -> PathBuf {
-    let parent_dir = path.parent().unwrap_or_else(|| Path::new(""));
-    let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("archive");
-    let sanitized: String = file_stem
-        .chars()
-        .filter(|c| !"<>:\"/\\|?*".contains(*c))
-        .collect();
+#[derive(clap::Args, Debug)]
+struct ExtractArgs {
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    #[command(flatten)]
+    options: ProfileArgs,
+}
 
-    let dest_dir = parent_dir.join(&sanitized);
-    if !dest_dir.exists() {
-        return dest_dir;
-    }
+// Command line counterpart of the `Profile` settings. A doc comment here
+// would become the about text of every command it is flattened into.
+#[derive(clap::Args, Debug)]
+struct ProfileArgs {
+    /// What to do when the destination folder already exists
+    #[arg(long, value_enum, default_value_t = ExistingPolicy::NewFolder)]
+    on_existing: ExistingPolicy,
+    /// Which file wins when merging into an existing folder
+    #[arg(long, value_enum, default_value_t = MergeRule::Overwrite)]
+    merge_rule: MergeRule,
+    /// How to handle archive entries that map to the same output path
+    #[arg(long, value_enum, default_value_t = CollisionPolicy::Rename)]
+    on_collision: CollisionPolicy,
+    /// Treat entry names differing only in case as colliding
+    #[arg(long)]
+    case_insensitive: bool,
+    /// Delete archives without extracting them when an identical copy was already extracted
+    #[arg(long)]
+    skip_identical: bool,
+    /// Keep archives instead of deleting them after extraction
+    #[arg(long)]
+    keep_archives: bool,
+    /// Root for relative destination templates [default: the archive's folder]
+    #[arg(short = 'o', long, visible_alias = "dest")]
+    output_dir: Option<PathBuf>,
+    /// Destination folder template; placeholders: {stem}, {ext}, {format}, {domain}, {date:<strftime>}
+    #[arg(long, default_value = "{stem}")]
+    dest_template: String,
+    /// Suffix for taken destination folders; `n` in braces is the counter, `{n:03}` zero-pads it
+    #[arg(long, default_value = DEFAULT_SUFFIX)]
+    collision_suffix: String,
+    /// Refuse archives that unpack to more than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    max_unpacked_size: Option<u64>,
+    /// Also extract archives found inside extracted archives, up to this many levels
+    #[arg(long, value_name = "LEVELS", default_value_t = 0)]
+    nested_depth: usize,
+    /// Upper bound in bytes for an extracted tree, the outer archive and nested ones included
+    #[arg(long, value_name = "BYTES")]
+    nested_budget: Option<u64>,
+    /// Only log what would be done, without writing or deleting anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Where the daemon moves archives that fail to extract [default: .unzipper-quarantine in the watched folder]
+    #[arg(long, value_name = "DIR")]
+    quarantine_dir: Option<PathBuf>,
+    /// Seconds an archive may keep being written to before it is put off for a retry
+    #[arg(long, value_name = "SECS", default_value_t = Profile::default().stability_timeout)]
+    stability_timeout: u64,
+    /// Download tools whose unfinished downloads are left alone
+    #[arg(
+        long = "partial-preset",
+        value_name = "PRESET",
+        value_delimiter = ',',
+        default_values = ["firefox", "chromium", "qbittorrent", "utorrent", "aria2"]
+    )]
+    partial_presets: Vec<PartialPreset>,
+    /// Name of unfinished downloads, `*` matching anything, or sidecar file marking one, e.g. `{name}.lock`
+    #[arg(long = "partial-pattern", value_name = "PATTERN", default_value = "*.tmp")]
+    partial_patterns: Vec<String>,
+    /// How the folder is watched; `auto` polls on network and FUSE filesystems
+    #[arg(long, value_enum, default_value_t = WatchBackend::Auto)]
+    watcher: WatchBackend,
+    /// Seconds between two looks through the folder when polling
+    #[arg(long, value_name = "SECS", default_value_t = Profile::default().poll_interval)]
+    poll_interval: u64,
+    /// When polling, also compare file contents, not just size and modification time
+    #[arg(long)]
+    poll_compare_contents: bool,
+}
 
-    let mut counter = 1;
-    loop {
-        let temp_dir_name = format!("{} ({})", sanitized, counter);
-        let temp_dir_path = parent_dir.join(&temp_dir_name);
-        if !temp_dir_path.exists() {
-            return temp_dir_path;
+impl ProfileArgs {
+    fn profile(&self, name: String, watch_path: PathBuf) -> Profile {
+        Profile {
+            name,
+            watch_path,
+            on_existing: self.on_existing,
+            merge_rule: self.merge_rule,
+            on_collision: self.on_collision,
+            case_insensitive: self.case_insensitive,
+            skip_identical: self.skip_identical,
+            keep_archive: self.keep_archives,
+            output_dir: self.output_dir.as_deref().map(platform::expand_home),
+            dest_template: self.dest_template.clone(),
+            collision_suffix: self.collision_suffix.clone(),
+            max_unpacked_bytes: self.max_unpacked_size,
+            nested_depth: self.nested_depth,
+            nested_budget: self.nested_budget,
+            dry_run: self.dry_run,
+            quarantine_dir: self.quarantine_dir.as_deref().map(platform::expand_home),
+            priority: 0,
+            stability_timeout: self.stability_timeout,
+            partial_presets: self.partial_presets.clone(),
+            partial_patterns: self.partial_patterns.clone(),
+            watcher: self.watcher,
+            poll_interval: self.poll_interval,
+            poll_compare_contents: self.poll_compare_contents,
         }
-        counter += 1;
     }
 }
 
-pub(crate) fn prepare_dest_dir(path: &Path) -> io::Result<PathBuf> {
-    let dest_dir = find_unique_dest_dir(path);
-    fs::create_dir_all(&dest_dir)?;
-    Ok(dest_dir)
-}
-
-fn is_temp_file_name(name: &str) -> bool {
-    let lower = name.to_ascii_lowercase();
-    lower.ends_with(".crdownload") || lower.ends_with(".part") || lower.ends_with(".tmp")
+impl Args {
+    fn profiles(&self) -> io::Result<Vec<Profile>> {
+        if let Some(config) = &self.config {
+            let mut profiles = profile::load_profiles(config)?;
+            for profile in &mut profiles {
+                profile.dry_run |= self.options.dry_run;
+            }
+            return Ok(profiles);
+        }
+        let watch_path = self
+            .watch_path
+            .clone()
+            .unwrap_or_else(platform::default_downloads_dir);
+        Ok(vec![self.options.profile(String::from("default"), watch_path)])
+    }
 }
 
-fn process_file(path: &Path, worker_id: usize) {
-    if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
-        if is_temp_file_name(name) {
-            return;
+/// Runs `unzipper extract`: processes each file once and exits with the code
+/// of the most severe failure class, or 0.
+fn run_extract(args: ExtractArgs) -> i32 {
+    let profile = args.options.profile(String::from("extract"), PathBuf::new());
+    let mut code = 0;
+    for file in &args.files {
+        let progress = if io::stderr().is_terminal() {
+            let name = file.file_name().unwrap_or(file.as_os_str()).to_string_lossy().into_owned();
+            progress_bar(name)
+        } else {
+            ProgressSink::default()
+        };
+        match extract_archive_with_progress(file, &profile, &progress).map(|report| (report.outcome, report.extraction)) {
+            Ok((Outcome::Extracted(dir), Some(extraction))) => println!(
+                "{} -> {} ({} files, {} bytes)",
+                file.display(),
+                dir.display(),
+                extraction.entries_written,
+                extraction.bytes_out
+            ),
+            Ok((Outcome::Extracted(dir), None)) => println!("{} -> {}", file.display(), dir.display()),
+            Ok((Outcome::Duplicate(dir), _)) => println!("{} == {}", file.display(), dir.display()),
+            Ok((Outcome::Planned(dir), _)) => println!("{} -> {} (dry run)", file.display(), dir.display()),
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                code = code.max(e.exit_code());
+            }
         }
     }
-    if let Err(e) = wait_until_stable(path, 5, std::time::Duration::from_millis(300)) {
-        eprintln!(
-            "[Worker {}] Skipping {} due to stability check error: {}",
-            worker_id,
-            path.display(),
-            e
-        );
-        return;
-    }
-    let ext = path
-        .extension()
-        .and_then(|e| e.to_str())
-        .map(|s| s.to_ascii_lowercase());
-    let ext = match ext {
-        Some(e) => e,
-        None => return,
-    };
+    code
+}
 
-    use crate::extractors::ArchiveExtractor;
-    let extractor: Option<Box<dyn ArchiveExtractor>> = match ext.as_str() {
-        "zip" => Some(Box::new(crate::extractors::zip::ZipExtractor)),
-        "tar" | "tgz" | "gz" | "tar.gz" | "taz" => {
-            Some(Box::new(crate::extractors::targz::TarGzExtractor))
-        }
-        "7z" => Some(Box::new(crate::extractors::sevenz::SevenZExtractor)),
-        "rar" => Some(Box::new(crate::extractors::rar::RarExtractor)),
-        _ => None,
+/// E.g. `45%, 120.0 of 300.0 MB, 12 of 40 files`.
+fn format_progress(progress: &Progress) -> String {
+    let mb = |bytes: u64| bytes as f64 / (1 << 20) as f64;
+    let bytes = match progress.bytes_total {
+        Some(total) => format!("{:.1} of {:.1} MB", mb(progress.bytes_done), mb(total)),
+        None => format!("{:.1} MB", mb(progress.bytes_done)),
     };
-    let Some(extractor) = extractor else { return };
-    println!(
-        "[Worker {}] Processing {} file: {}",
-        worker_id,
-        ext,
-        path.display()
-    );
-
-    let dest_dir = match prepare_dest_dir(path) {
-        Ok(dir) => dir,
-        Err(e) => {
-            eprintln!(
-                "[Worker {}] Failed to prepare destination directory for {}: {}",
-                worker_id,
-                path.display(),
-                e
-            );
-            return;
-        }
+    let files = match progress.entries_total {
+        Some(total) => format!("{} of {} files", progress.entries_done, total),
+        None => format!("{} files", progress.entries_done),
     };
-    if let Err(e) = extractor.extract(path, &dest_dir, worker_id) {
-        eprintln!(
-            "[Worker {}] Error extracting {}: {}",
-            worker_id,
-            path.display(),
-            e
-        );
-        return;
+    match progress.fraction() {
+        Some(fraction) => format!("{:.0}%, {}, {}", fraction * 100.0, bytes, files),
+        None => format!("{}, {}", bytes, files),
     }
-    if let Err(e) = wait_until_stable(path, 5, std::time::Duration::from_millis(300)) {
-        eprintln!(
-            "[Worker {}] Failed to achieve stability on {}: {}",
-            worker_id,
-            path.display(),
-            e
-        );
-        return;
-    }
-    delete_file(path, worker_id);
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use std::fs;
-
-    fn temp_dir() -> PathBuf {
-        let mut d = std::env::temp_dir();
-        d.push(format!(
-            "unzipper_test_{}_{}",
-            std::process::id(),
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos()
-        ));
-        std::fs::create_dir_all(&d).unwrap();
-        d
-    }
+/// Draws the progress of extracting `name` as a bar on stderr, wiped again
+/// once the extraction is finished.
+fn progress_bar(name: String) -> ProgressSink {
+    const WIDTH: usize = 24;
+    ProgressSink::new(move |progress| {
+        let mut stderr = io::stderr().lock();
+        if progress.finished {
+            let _ = write!(stderr, "\r\x1b[2K");
+        } else {
+            let bar = match progress.fraction() {
+                Some(fraction) => {
+                    let filled = (fraction * WIDTH as f64) as usize;
+                    format!(" [{}{}]", "#".repeat(filled), "-".repeat(WIDTH - filled))
+                }
+                None => String::new(),
+            };
+            let _ = write!(stderr, "\r\x1b[2K{}{} {}", name, bar, format_progress(progress));
+        }
+        let _ = stderr.flush();
+    })
+    .replacing_entry_log()
+}
 
-    #[test]
-    fn test_is_temp_file_name() {
-        assert!(is_temp_file_name("file.part"));
-        assert!(is_temp_file_name("file.tmp"));
-        assert!(is_temp_file_name("Some.CRDOWNLOAD"));
-        assert!(!is_temp_file_name("archive.7z"));
-        assert!(!is_temp_file_name("normal.zip"));
-    }
+fn format_entry(entry: &EntryInfo) -> String {
+    let modified = entry
+        .modified
+        .map(|t| chrono::DateTime::<chrono::Local>::from(t).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| String::from("-"));
+    let compressed = entry
+        .compressed_size
+        .map_or_else(|| String::from("-"), |c| c.to_string());
+    format!(
+        "{:19}  {:>12}  {:>12}  {}{}  {}",
+        modified,
+        entry.size,
+        compressed,
+        if entry.is_dir { 'D' } else { '.' },
+        if entry.encrypted { 'E' } else { '.' },
+        entry.path.display()
+    )
+}
 
-    #[test]
-    fn test_wait_until_stable_on_existing_file() {
-        let mut p = std::env::temp_dir();
-        p.push(format!(
-            "unzipper_test_{}_{}.tmp",
-            std::process::id(),
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos()
-        ));
-        {
-            let mut f = std::fs::File::create(&p).expect("create temp file");
-            writeln!(f, "hello").unwrap();
+/// Runs `unzipper list`.
+fn run_list(args: InspectArgs) -> i32 {
+    let mut code = 0;
+    for file in &args.files {
+        let listed = inspect(file).and_then(|(format, extractor)| {
+            let entries = extractor.entries(file).map_err(ProcessError::Extract)?;
+            Ok((format, entries))
+        });
+        match listed {
+            Ok((format, entries)) => {
+                println!("{} ({})", file.display(), format);
+                println!("{:19}  {:>12}  {:>12}  Attr  Name", "Modified", "Size", "Compressed");
+                for entry in &entries {
+                    println!("{}", format_entry(entry));
+                }
+                let files = entries.iter().filter(|e| !e.is_dir).count();
+                let bytes: u64 = entries.iter().map(|e| e.size).sum();
+                println!("{} files, {} bytes\n", files, bytes);
+            }
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                code = code.max(e.exit_code());
+            }
         }
-        let res = wait_until_stable(&p, 3, std::time::Duration::from_millis(50));
-        std::fs::remove_file(&p).ok();
-        assert!(res.is_ok());
     }
+    code
+}
 
-    fn create_sample_zip(dir: &Path) -> PathBuf {
-        let zip_path = dir.join("sample.zip");
-        let file_path = dir.join("inner.txt");
-        std::fs::write(&file_path, b"hi").unwrap();
-        let f = std::fs::File::create(&zip_path).unwrap();
-        let mut z = zip::ZipWriter::new(f);
-        let options =
-            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
-        z.start_file("inner.txt", options).unwrap();
-        z.write_all(b"hi").unwrap();
-        z.finish().unwrap();
-        zip_path
+/// Runs `unzipper test`.
+fn run_test(args: InspectArgs) -> i32 {
+    let mut code = 0;
+    for file in &args.files {
+        let tested = inspect(file)
+            .and_then(|(format, extractor)| Ok((format, extractor.test(file).map_err(ProcessError::Extract)?)));
+        match tested {
+            Ok((format, count)) => println!("{}: OK ({}, {} entries)", file.display(), format, count),
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                code = code.max(e.exit_code());
+            }
+        }
     }
+    code
+}
 
-    #[test]
-    fn test_find_unique_dest_dir_with_conflict() {
-        let td = temp_dir();
-        let conflicting_dir = td.join("test_archive");
-        fs::create_dir(&conflicting_dir).unwrap();
-        let archive_path = td.join("test_archive.zip");
-        let unique_path = find_unique_dest_dir(&archive_path);
-        assert_eq!(unique_path, td.join("test_archive (1)"));
-        fs::remove_dir_all(&td).ok();
-    }
+/// Runs `unzipper ctl`: sends one request to the daemon and prints its answer.
+#[cfg(unix)]
+fn run_ctl(args: CtlArgs) -> i32 {
+    use unzipper::control::{self, Request, Response};
 
-    #[test]
-    fn test_process_file_zip_extracts_and_deletes_archive() {
-        let td = temp_dir();
-        let zip_path = create_sample_zip(&td);
-        process_file(&zip_path, 1);
-        assert!(!zip_path.is_file());
-        let extracted_dir = td.join("sample");
-        assert!(extracted_dir.exists());
-        let inner = extracted_dir.join("inner.txt");
-        assert_eq!(std::fs::read_to_string(inner).unwrap(), "hi");
-        std::fs::remove_dir_all(&td).ok();
+    let absolute = |path: PathBuf| std::path::absolute(&path).unwrap_or(path);
+    let request = match args.command {
+        CtlCommand::Status => Request::Status,
+        CtlCommand::Pause => Request::Pause,
+        CtlCommand::Resume => Request::Resume,
+        CtlCommand::Enqueue { path } => Request::Enqueue { path: absolute(path) },
+        CtlCommand::Retry { path } => Request::Retry { path: absolute(path) },
+        CtlCommand::Rescan => Request::Rescan,
+        CtlCommand::Stop => Request::Stop,
+    };
+    let socket = args.socket.unwrap_or_else(control::default_socket_path);
+    match control::send(&socket, &request) {
+        Ok(Response::Status(status)) => {
+            println!(
+                "{}, {} archive(s) queued",
+                if status.paused { "Paused" } else { "Running" },
+                status.queued
+            );
+            for running in &status.running {
+                println!(
+                    "Worker {}: {} ({}, {:.0}s)",
+                    running.worker,
+                    running.archive.display(),
+                    running.profile,
+                    running.elapsed_secs
+                );
+                if let Some(progress) = &running.progress {
+                    println!("    {}", format_progress(progress));
+                }
+            }
+            0
+        }
+        Ok(Response::Done(message)) => {
+            println!("{}", message);
+            0
+        }
+        Ok(Response::Error(message)) => {
+            eprintln!("{}", message);
+            1
+        }
+        Err(e) => {
+            eprintln!("Could not reach the daemon at {}: {}", socket.display(), e);
+            1
+        }
     }
+}
 
-    #[test]
-    fn test_process_file_ignores_temp_extensions() {
-        let td = temp_dir();
-        let tmp = td.join("ongoing.zip.part");
-        std::fs::write(&tmp, b"x").unwrap();
-        process_file(&tmp, 5);
-        assert!(tmp.exists());
-        std::fs::remove_dir_all(&td).ok();
-    }
+#[cfg(not(unix))]
+fn run_ctl(_args: CtlArgs) -> i32 {
+    eprintln!("The control socket is only available on Unix.");
+    1
 }
 
-fn main() -> Result<()> {
-    println!("### UNIQUE VERSION: 2025-09-08T02:05:00Z ###");
+fn main() -> notify::Result<()> {
     let args = Args::parse();
-    let lock_file_path = std::env::temp_dir().join("unzipper.lock");
-    let lock_file = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(&lock_file_path)
-        .expect("Could not create lock file.");
-    if let Err(_e) = lock_file.try_lock_exclusive() {
-        eprintln!("Another instance of unzipper is already running. Exiting.");
-        return Ok(());
+    match args.command {
+        Some(Command::Extract(extract_args)) => std::process::exit(run_extract(*extract_args)),
+        Some(Command::List(inspect_args)) => std::process::exit(run_list(inspect_args)),
+        Some(Command::Test(inspect_args)) => std::process::exit(run_test(inspect_args)),
+        Some(Command::Ctl(ctl_args)) => std::process::exit(run_ctl(ctl_args)),
+        None => {}
     }
-    let dir_to_watch = args
-        .watch_path
-        .unwrap_or_else(|| platform::default_downloads_dir());
-    println!("[Main] Target directory set to: {}", dir_to_watch.display());
-    if !dir_to_watch.exists() {
-        eprintln!("[Main] Error: Watch directory does not exist.");
-        return Ok(());
-    }
-    let (tx_to_workers, rx_from_main) = mpsc::channel::<PathBuf>();
-    let rx_from_main = Arc::new(Mutex::new(rx_from_main));
-    let (tx_removals, rx_removals) = mpsc::channel::<PathBuf>();
-    let shutting_down = Arc::new(AtomicBool::new(false));
-    let sd_cb_removals = Arc::clone(&shutting_down);
-    let sd_cb_removals_for_thread = Arc::clone(&shutting_down);
-    let watcher_tx_removals = tx_removals.clone();
-    let dir_to_watch_removals = dir_to_watch.clone();
-    thread::spawn(move || {
-        println!("[REMOVAL_CHECK] Starting up.");
-        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
-            if sd_cb_removals.load(Ordering::SeqCst) {
-                return;
-            }
-            if let Ok(event) = res {
-                if event.kind == EventKind::Remove(notify::event::RemoveKind::Any) {
-                    for path in event.paths {
-                        if is_processable_path(path.as_path()) {
-                            watcher_tx_removals
-                                .send(path)
-                                .expect("Failed to send removal event to main thread");
-                        }
-                    }
-                }
-            }
-        })
-            .expect("Failed to create removal watcher");
-        watcher
-            .watch(&dir_to_watch_removals, RecursiveMode::NonRecursive)
-            .expect("Failed to start removal watcher");
-        while !sd_cb_removals_for_thread.load(Ordering::SeqCst) {
-            thread::park_timeout(std::time::Duration::from_millis(200));
+    println!("### UNIQUE VERSION: 2025-09-08T02:05:00Z ###");
+    let profiles = match args.profiles() {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            eprintln!("[Main] Error: Failed to load config: {}", e);
+            return Ok(());
         }
-        drop(watcher);
-    });
-    const NUM_WORKERS: usize = 4;
-    for i in 0..NUM_WORKERS {
-        let worker_rx = Arc::clone(&rx_from_main);
-        let sd = Arc::clone(&shutting_down);
-        let tx_removals_worker = tx_removals.clone();
-        thread::spawn(move || {
-            println!("[Worker {}] Starting up.", i);
-            loop {
-                if sd.load(Ordering::SeqCst) {
-                    println!("[Worker {}] Shutdown flag set. Exiting.", i);
-                    break;
-                }
-                let path_result = worker_rx.lock().unwrap().recv();
-                match path_result {
-                    Ok(path) => {
-                        process_file(&path, i);
-                        tx_removals_worker
-                            .send(path)
-                            .expect("Failed to send delete signal to main thread");
-                    }
-                    Err(_) => {
-                        println!("[Worker {}] Channel closed. Shutting down.", i);
-                        break;
-                    }
-                }
-            }
+    };
+    // A pure dry run writes nothing, so it may run next to the real daemon.
+    let lock_file = if profiles.iter().all(|p| p.dry_run) {
+        println!("[Main] Dry run: nothing will be extracted or deleted.");
+        None
+    } else {
+        let lock_file_path = std::env::temp_dir().join("unzipper.lock");
+        let lock_file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_file_path)
+            .expect("Could not create lock file.");
+        if let Err(_e) = lock_file.try_lock_exclusive() {
+            eprintln!("Another instance of unzipper is already running. Exiting.");
+            return Ok(());
+        }
+        Some(lock_file)
+    };
+    let mut builder = Daemon::builder()
+        .retry(RetryPolicy {
+            max_attempts: args.retries.max(1),
+            initial_delay: Duration::from_secs(args.retry_delay),
+            ..RetryPolicy::default()
+        })
+        .shutdown_grace(Duration::from_secs(args.shutdown_grace))
+        .large_archives(LargeArchives {
+            threshold: args.large_size.saturating_mul(1 << 20),
+            max_concurrent: args.max_large.max(1),
         });
+    if let Some(secs) = args.rescan_interval {
+        builder = builder.rescan_every(Duration::from_secs(secs.max(1)));
     }
-    println!(
-        "[Main] Checking for existing archives in {}...",
-        dir_to_watch.display()
-    );
-    for entry in WalkDir::new(&dir_to_watch)
-        .max_depth(1)
-        .into_iter()
-        .filter_map(std::result::Result::ok)
+    if let Some(workers) = args.workers {
+        builder = builder.workers(workers);
+    }
+    if let Some(path) = &args.audit_log {
+        builder = builder.audit_log(platform::expand_home(path));
+    }
+    #[cfg(unix)]
     {
-        let path = entry.path();
-        if is_processable_path(path) {
-            println!(
-                "[Main] Found existing archive: {}. Sending to worker.",
-                path.display()
-            );
-            tx_to_workers
-                .send(path.to_path_buf())
-                .expect("Failed to send path to worker thread");
+        let socket = match &args.control_socket {
+            Some(path) => Some(platform::expand_home(path)),
+            None if lock_file.is_some() => Some(unzipper::control::default_socket_path()),
+            None => None,
+        };
+        if let Some(socket) = socket {
+            builder = builder.control_socket(socket);
         }
     }
-    println!("[Main] Finished scanning for existing archives.");
-    let watcher_tx_to_workers = tx_to_workers.clone();
-    let sd_cb_main_watcher = Arc::clone(&shutting_down);
-    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
-        if sd_cb_main_watcher.load(Ordering::SeqCst) {
-            return;
-        }
-        match res {
-            Ok(event) => match event.kind {
-                EventKind::Create(_) | EventKind::Modify(notify::event::ModifyKind::Name(_)) => {
-                    for path in event.paths {
-                        if is_processable_path(&path) {
-                            println!(
-                                "[Main] Detected file event for: {}. Sending to worker.",
-                                path.display()
-                            );
-                            watcher_tx_to_workers
-                                .send(path)
-                                .expect("Failed to send path to worker thread");
-                        }
-                    }
-                }
-                _ => (),
-            },
-            Err(e) => eprintln!("[Main] Watch error: {:?}", e),
+    for profile in profiles {
+        println!(
+            "[Main] Target directory set to: {} (profile {})",
+            profile.watch_path.display(),
+            profile.name
+        );
+        builder = builder.profile(profile);
+    }
+    let daemon = match builder.build() {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            eprintln!("[Main] Error: {}", e);
+            return Ok(());
         }
-    })?;
-    println!(
-        "[Main] Watching directory: {} for new archives...",
-        dir_to_watch.display()
-    );
-    watcher.watch(&dir_to_watch, RecursiveMode::NonRecursive)?;
-    let sd_sig = Arc::clone(&shutting_down);
+    };
+    let handle = daemon.handle();
     ctrlc::set_handler(move || {
-        if !sd_sig.swap(true, Ordering::SeqCst) {
+        if handle.stop() {
             eprintln!("\n[Main] Ctrl+C received. Shutting down gracefully...");
         }
     })
         .expect("Error setting Ctrl+C handler");
-    drop(tx_to_workers);
-    drop(tx_removals);
-    while !shutting_down.load(Ordering::SeqCst) {
-        if let Ok(removed_path) = rx_removals.try_recv() {
-            println!(
-                "[Main] Confirmed file deletion via event: {}",
-                removed_path.display()
-            );
-        }
-        thread::park_timeout(std::time::Duration::from_millis(200));
-    }
-    drop(watcher);
-    thread::sleep(std::time::Duration::from_millis(200));
-    println!("[Main] Shutdown complete.");
-    Ok(())
+    daemon.run()
 }
@@ -0,0 +1,97 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::dest::unique_dir;
+use crate::extractors::{self, ExtractOptions, ExtractReport};
+use crate::profile::Profile;
+use crate::template::sanitize_component;
+
+fn tree_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn find_archives(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file() && extractors::for_path(e.path()).is_some())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Extracts archives found inside the freshly extracted `root`, each next to
+/// itself, down to `profile.nested_depth` levels. The whole tree may grow to
+/// at most `profile.nested_budget` bytes, counting what is already in `root`;
+/// rar archives are held to the sizes they declare. Inner archives are
+/// deleted once extracted; ones that fail are left in place and noted as a
+/// warning in `report`.
+/// Stops early once `outer.cancel` is set.
+pub fn extract_nested(
+    root: &Path,
+    profile: &Profile,
+    worker_id: usize,
+    outer: &ExtractOptions,
+    report: &mut ExtractReport,
+) -> io::Result<()> {
+    let mut total = tree_size(root);
+    let mut level = vec![root.to_path_buf()];
+    for depth in 1..=profile.nested_depth {
+        let mut next_level = Vec::new();
+        for dir in &level {
+            for inner in find_archives(dir) {
+                if outer.cancel.is_cancelled() {
+                    return Ok(());
+                }
+                let Some((format, extractor)) = extractors::for_path(&inner) else { continue };
+                let mut options = outer.clone();
+                if let Some(budget) = profile.nested_budget {
+                    let remaining = budget.saturating_sub(total);
+                    options.max_unpacked_bytes =
+                        Some(options.max_unpacked_bytes.map_or(remaining, |max| max.min(remaining)));
+                }
+                let stem = inner.file_stem().and_then(|s| s.to_str()).unwrap_or("archive");
+                let parent = inner.parent().unwrap_or(dir);
+                let dest = unique_dir(&parent.join(sanitize_component(stem)), &profile.collision_suffix);
+                println!(
+                    "[Worker {}] Extracting nested {} archive (depth {}): {}",
+                    worker_id,
+                    format,
+                    depth,
+                    inner.display()
+                );
+                fs::create_dir_all(&dest)?;
+                match extractor.extract(&inner, &dest, worker_id, &options) {
+                    Ok(nested) => report.absorb(nested),
+                    Err(e) => {
+                        eprintln!(
+                            "[Worker {}] Error extracting nested {}: {}",
+                            worker_id,
+                            inner.display(),
+                            e
+                        );
+                        report.warn(format!("Could not extract nested {}: {}", inner.display(), e));
+                        let _ = fs::remove_dir_all(&dest);
+                        continue;
+                    }
+                }
+                let archive_len = fs::metadata(&inner).map(|m| m.len()).unwrap_or(0);
+                fs::remove_file(&inner)?;
+                total = total + tree_size(&dest) - archive_len.min(total);
+                next_level.push(dest);
+            }
+        }
+        if next_level.is_empty() {
+            break;
+        }
+        level = next_level;
+    }
+    Ok(())
+}
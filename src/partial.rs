@@ -0,0 +1,147 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Download tools whose unfinished downloads are recognised by name.
+///
+/// wget and curl need no preset: they write under the final name, and
+/// [`crate::process::wait_until_stable`] holds such files back while they are
+/// still open for writing.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PartialPreset {
+    /// `<name>.part`, next to an empty placeholder under the final name.
+    Firefox,
+    /// `<name>.crdownload`, also used by Chrome, Edge, Brave and Opera.
+    Chromium,
+    /// `<name>.!qB` while "Append .!qB extension to incomplete files" is on.
+    Qbittorrent,
+    /// `<name>.!ut`.
+    Utorrent,
+    /// Writes under the final name and keeps `<name>.aria2` next to it until done.
+    Aria2,
+}
+
+impl PartialPreset {
+    pub const ALL: [PartialPreset; 5] = [
+        PartialPreset::Firefox,
+        PartialPreset::Chromium,
+        PartialPreset::Qbittorrent,
+        PartialPreset::Utorrent,
+        PartialPreset::Aria2,
+    ];
+
+    pub fn patterns(self) -> &'static [&'static str] {
+        match self {
+            PartialPreset::Firefox => &["*.part", "{name}.part"],
+            PartialPreset::Chromium => &["*.crdownload"],
+            PartialPreset::Qbittorrent => &["*.!qB"],
+            PartialPreset::Utorrent => &["*.!ut"],
+            PartialPreset::Aria2 => &["*.aria2", "{name}.aria2"],
+        }
+    }
+}
+
+/// Tells unfinished downloads apart. A pattern either matches the file name,
+/// with `*` standing for any run of characters, or, if it contains `{name}`,
+/// names a sidecar file whose existence marks the file as unfinished. Name
+/// patterns ignore case; sidecar names are taken as written.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartialRules {
+    names: Vec<String>,
+    sidecars: Vec<(String, String)>,
+}
+
+impl PartialRules {
+    pub fn new<'a>(presets: &[PartialPreset], patterns: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut rules = PartialRules::default();
+        let preset_patterns = presets.iter().flat_map(|p| p.patterns().iter().copied());
+        for pattern in preset_patterns.chain(patterns) {
+            match pattern.split_once("{name}") {
+                Some((prefix, suffix)) => rules.sidecars.push((prefix.to_string(), suffix.to_string())),
+                None => rules.names.push(pattern.to_lowercase()),
+            }
+        }
+        rules
+    }
+
+    /// Whether `name` itself marks an unfinished download.
+    pub fn matches_name(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.names.iter().any(|pattern| glob_match(pattern, &name))
+    }
+
+    /// An existing sidecar showing that `path` is still being downloaded.
+    pub fn sidecar_of(&self, path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?.to_str()?;
+        self.sidecars
+            .iter()
+            .map(|(prefix, suffix)| path.with_file_name(format!("{}{}{}", prefix, name, suffix)))
+            .find(|sidecar| sidecar.is_file())
+    }
+
+    /// The download `sidecar` belongs to, if it is named like one of the sidecars.
+    pub fn download_of(&self, sidecar: &Path) -> Option<PathBuf> {
+        let name = sidecar.file_name()?.to_str()?;
+        self.sidecars.iter().find_map(|(prefix, suffix)| {
+            let download = name.strip_prefix(prefix.as_str())?.strip_suffix(suffix.as_str())?;
+            (!download.is_empty()).then(|| sidecar.with_file_name(download))
+        })
+    }
+}
+
+/// Matches `name` against `pattern`, where `*` stands for any run of characters.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.part", "sample.zip.part"));
+        assert!(glob_match("unconfirmed *.crdownload", "unconfirmed 123.crdownload"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(!glob_match("*.part", "sample.zip"));
+        assert!(!glob_match("a*bc", "acb"));
+        assert!(glob_match("exact.zip", "exact.zip"));
+        assert!(!glob_match("exact.zip", "exact.zip2"));
+    }
+
+    #[test]
+    fn test_partial_rules() {
+        let td = std::env::temp_dir().join(format!("unzipper_partial_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        let rules = PartialRules::new(&PartialPreset::ALL, ["*.tmp"]);
+        assert!(rules.matches_name("Movie.ZIP.!qB"));
+        assert!(rules.matches_name("sample.zip.crdownload"));
+        assert!(rules.matches_name("x.tmp"));
+        assert!(!rules.matches_name("sample.zip"));
+
+        let download = td.join("Bundle.zip");
+        fs::write(&download, b"").unwrap();
+        assert_eq!(rules.sidecar_of(&download), None);
+        fs::write(td.join("Bundle.zip.aria2"), b"").unwrap();
+        assert_eq!(rules.sidecar_of(&download), Some(td.join("Bundle.zip.aria2")));
+        assert_eq!(rules.download_of(&td.join("Bundle.zip.aria2")), Some(download));
+        assert_eq!(rules.download_of(&td.join("Bundle.zip")), None);
+        fs::remove_dir_all(&td).ok();
+    }
+}
@@ -28,3 +28,127 @@ fn linux_downloads_dir() -> PathBuf {
 
     Path::new(&home).join("Downloads")
 }
+
+/// Reads the `user.xdg.origin.url` attribute browsers set on downloads.
+pub fn origin_url(path: &Path) -> Option<String> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let name = c"user.xdg.origin.url";
+    let mut buf = vec![0u8; 4096];
+    // SAFETY: both strings are NUL-terminated and `buf` is valid for `buf.len()` bytes.
+    let len = unsafe {
+        libc::getxattr(
+            c_path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        )
+    };
+    if len <= 0 {
+        return None;
+    }
+    buf.truncate(len as usize);
+    String::from_utf8(buf).ok()
+}
+
+/// Scans `/proc/*/fd` for descriptors of `path` opened for writing. Processes
+/// of other users can't be inspected and are skipped.
+pub fn writers_of(path: &Path) -> Option<Vec<u32>> {
+    let target = fs::canonicalize(path).ok()?;
+    let mut writers = Vec::new();
+    for process in fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = process.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if fs::read_link(fd.path()).ok().as_deref() != Some(target.as_path()) {
+                continue;
+            }
+            if opened_for_writing(&process.path().join("fdinfo").join(fd.file_name())) {
+                writers.push(pid);
+                break;
+            }
+        }
+    }
+    Some(writers)
+}
+
+/// Reads the access mode from the `flags:` line of a `/proc/<pid>/fdinfo/<fd>` file.
+fn opened_for_writing(fdinfo: &Path) -> bool {
+    let Ok(text) = fs::read_to_string(fdinfo) else {
+        return false;
+    };
+    text.lines()
+        .find_map(|line| line.strip_prefix("flags:"))
+        .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+        .is_some_and(|flags| flags & libc::O_ACCMODE as u32 != libc::O_RDONLY as u32)
+}
+
+/// `statfs` magic numbers of filesystems whose remote changes inotify misses.
+const NETWORK_FS_MAGIC: [u32; 10] = [
+    0x6969,     // NFS
+    0x517B,     // SMB
+    0xFF53_4D42, // CIFS
+    0xFE53_4D42, // SMB2
+    0x6573_5546, // FUSE, e.g. sshfs
+    0x0102_1997, // 9p
+    0x5346_414F, // AFS
+    0x7375_7245, // Coda
+    0x00C3_6400, // Ceph
+    0x564C,     // NCP
+];
+
+pub fn is_network_fs(path: &Path) -> Option<bool> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `stat` is valid for writing a `statfs`.
+    if unsafe { libc::statfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: `statfs` succeeded, so it filled in `stat`.
+    let stat = unsafe { stat.assume_init() };
+    Some(NETWORK_FS_MAGIC.contains(&(stat.f_type as u32)))
+}
+
+#[cfg(target_os = "linux")]
+pub fn exchange(a: &Path, b: &Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let to_c = |p: &Path| CString::new(p.as_os_str().as_bytes()).map_err(std::io::Error::other);
+    let (a, b) = (to_c(a)?, to_c(b)?);
+    // SAFETY: both paths are NUL-terminated and outlive the call.
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_network_fs() {
+        assert_eq!(is_network_fs(&std::env::temp_dir()), Some(false));
+        assert_eq!(is_network_fs(Path::new("/nonexistent/unzipper")), None);
+    }
+}
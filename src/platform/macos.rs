@@ -1,7 +1,24 @@
 use std::env;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 
 pub fn default_downloads_dir() -> PathBuf {
     let home = env::var("HOME").unwrap_or_else(|_| String::from("."));
     Path::new(&home).join("Downloads")
 }
+
+/// Reads the first `kMDItemWhereFroms` entry Spotlight records for downloads.
+pub fn origin_url(path: &Path) -> Option<String> {
+    let out = Command::new("mdls")
+        .arg("-raw")
+        .arg("-name")
+        .arg("kMDItemWhereFroms")
+        .arg(path)
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&out.stdout);
+    text.lines()
+        .map(|line| line.trim().trim_end_matches(',').trim_matches('"'))
+        .find(|line| line.contains("://"))
+        .map(str::to_string)
+}
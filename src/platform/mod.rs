@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[cfg(windows)]
 pub fn default_downloads_dir() -> PathBuf {
@@ -15,6 +15,75 @@ pub fn default_downloads_dir() -> PathBuf {
     linux::default_downloads_dir()
 }
 
+#[cfg(windows)]
+pub fn home_dir() -> PathBuf {
+    windows::home_dir()
+}
+
+#[cfg(unix)]
+pub fn home_dir() -> PathBuf {
+    PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| String::from(".")))
+}
+
+/// URL the file was downloaded from, as recorded by the browser.
+#[cfg(windows)]
+pub fn origin_url(path: &Path) -> Option<String> {
+    windows::origin_url(path)
+}
+
+#[cfg(target_os = "macos")]
+pub fn origin_url(path: &Path) -> Option<String> {
+    macos::origin_url(path)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn origin_url(path: &Path) -> Option<String> {
+    linux::origin_url(path)
+}
+
+/// Processes holding `path` open for writing, or `None` where that can't be told.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn writers_of(path: &Path) -> Option<Vec<u32>> {
+    linux::writers_of(path)
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn writers_of(_path: &Path) -> Option<Vec<u32>> {
+    None
+}
+
+/// Whether `path` is on a network or FUSE filesystem, where changes made by
+/// other hosts raise no events; `None` where that can't be told.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn is_network_fs(path: &Path) -> Option<bool> {
+    linux::is_network_fs(path)
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn is_network_fs(_path: &Path) -> Option<bool> {
+    None
+}
+
+/// Swaps the entries at `a` and `b` in one step, so neither name is ever
+/// missing. Fails where the system or filesystem can't do that.
+#[cfg(target_os = "linux")]
+pub fn exchange(a: &Path, b: &Path) -> std::io::Result<()> {
+    linux::exchange(a, b)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn exchange(_a: &Path, _b: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Replaces a leading `~` with the user's home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => home_dir().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
 #[cfg(windows)]
 mod windows;
 
@@ -1,7 +1,23 @@
 use std::env;
+use std::fs;
 use std::path::{Path, PathBuf};
 
-pub fn default_downloads_dir() -> PathBuf {
+pub fn home_dir() -> PathBuf {
     let home = env::var("USERPROFILE").unwrap_or_else(|_| String::from("."));
-    Path::new(&home).join("Downloads")
+    PathBuf::from(home)
+}
+
+pub fn default_downloads_dir() -> PathBuf {
+    home_dir().join("Downloads")
+}
+
+/// Reads `HostUrl` from the `Zone.Identifier` stream browsers attach to downloads.
+pub fn origin_url(path: &Path) -> Option<String> {
+    let mut stream = path.as_os_str().to_owned();
+    stream.push(":Zone.Identifier");
+    let contents = fs::read_to_string(stream).ok()?;
+    contents
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("HostUrl="))
+        .map(str::to_string)
 }
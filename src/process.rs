@@ -0,0 +1,866 @@
+use notify::event::{AccessKind, AccessMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dest::{find_identical_copy, merge_overwrites, Destination, ExistingPolicy};
+use crate::extractors::error::is_transient_io;
+use crate::extractors::{self, ArchiveExtractor, CancelToken, ExtractError, ExtractOptions, ExtractReport, ProgressSink};
+use crate::nested;
+use crate::platform;
+use crate::profile::Profile;
+
+fn delete_file(path: &Path, worker_id: usize) {
+    println!(
+        "[Worker {}] Trying to delete file: {}",
+        worker_id,
+        path.display()
+    );
+    let _ = fs::remove_file(path);
+}
+
+/// How often a settling file is looked at again when no close event arrives.
+const STABLE_POLL: Duration = Duration::from_millis(300);
+
+fn is_close_write(kind: &EventKind) -> bool {
+    matches!(kind, EventKind::Access(AccessKind::Close(AccessMode::Write)))
+}
+
+/// Waits until nothing writes to `path` any more: no process holds it open
+/// for writing (where [`platform::writers_of`] can tell) and either a writer
+/// was just seen closing it or its size and modification time stayed put for
+/// one poll. With `settle`, for files other hosts may write to, neither of
+/// the local signals counts and size and modification time have to stay put
+/// for at least that long. Fails with [`io::ErrorKind::TimedOut`] if that
+/// doesn't happen within `timeout`, and with [`io::ErrorKind::Interrupted`]
+/// as soon as `cancel` is set.
+pub fn wait_until_stable(
+    path: &Path,
+    timeout: Duration,
+    settle: Option<Duration>,
+    cancel: &CancelToken,
+) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::channel();
+    // Without a watcher the file is only polled.
+    let _watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            let _ = tx.send(event.kind);
+        }
+    })
+    .and_then(|mut watcher| watcher.watch(path, RecursiveMode::NonRecursive).map(|_| watcher))
+    .ok();
+    let mut previous = None;
+    let mut unchanged_since = Instant::now();
+    let mut closed = false;
+    loop {
+        if cancel.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        let metadata = fs::metadata(path)?;
+        let current = (metadata.len(), metadata.modified().ok());
+        let writers = platform::writers_of(path).unwrap_or_default();
+        if previous != Some(current) {
+            unchanged_since = Instant::now();
+        }
+        let settled = match settle {
+            Some(settle) => previous == Some(current) && unchanged_since.elapsed() >= settle,
+            None => closed || previous == Some(current),
+        };
+        if writers.is_empty() && settled {
+            return Ok(());
+        }
+        previous = Some(current);
+        let now = Instant::now();
+        if now >= deadline {
+            let reason = match writers.as_slice() {
+                [] => String::from("it kept changing"),
+                pids => format!(
+                    "it is still open for writing by process {}",
+                    pids.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+                ),
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("gave up after {}s because {}", timeout.as_secs(), reason),
+            ));
+        }
+        closed = false;
+        let wake = now + STABLE_POLL.min(deadline - now);
+        loop {
+            match rx.recv_timeout(wake.saturating_duration_since(Instant::now())) {
+                Ok(kind) if is_close_write(&kind) => {
+                    closed = true;
+                    break;
+                }
+                Ok(_) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    thread::sleep(wake.saturating_duration_since(Instant::now()));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// How `process_file` finished when it didn't fail.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Extracted(PathBuf),
+    Duplicate(PathBuf),
+    Planned(PathBuf),
+}
+
+/// What happened to one archive.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Report {
+    pub archive: PathBuf,
+    /// Format label of the extractor that handled it, e.g. `zip` or `tar.gz`.
+    pub format: String,
+    pub outcome: Outcome,
+    /// Details of the extraction, nested archives included; `None` unless something was extracted.
+    pub extraction: Option<ExtractReport>,
+}
+
+/// Why `process_file` gave up on an archive; each class maps to its own exit
+/// code for the one-shot `extract` command.
+#[derive(Debug)]
+pub enum ProcessError {
+    Missing,
+    NotAnArchive,
+    /// A download tool's sidecar file shows the download isn't finished.
+    Incomplete,
+    Unstable(io::Error),
+    Destination(io::Error),
+    Extract(ExtractError),
+}
+
+impl ProcessError {
+    /// Whether the daemon should try the archive again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ProcessError::Missing | ProcessError::NotAnArchive | ProcessError::Incomplete => false,
+            ProcessError::Unstable(_) => true,
+            ProcessError::Destination(e) => is_transient_io(e),
+            ProcessError::Extract(e) => e.is_transient(),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ProcessError::Missing => 3,
+            ProcessError::NotAnArchive => 4,
+            ProcessError::Unstable(_) => 5,
+            ProcessError::Destination(_) => 6,
+            ProcessError::Extract(_) => 7,
+            ProcessError::Incomplete => 8,
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessError::Missing => write!(f, "no such file"),
+            ProcessError::NotAnArchive => write!(f, "not a supported archive"),
+            ProcessError::Incomplete => write!(f, "download still in progress"),
+            ProcessError::Unstable(e) => write!(f, "file did not stabilise: {}", e),
+            ProcessError::Destination(e) => write!(f, "destination error: {}", e),
+            ProcessError::Extract(e) => write!(f, "extraction failed: {}", e),
+        }
+    }
+}
+
+/// Extracts `path` according to `profile`, reporting how far it got to
+/// `progress`. Once `cancel` is set the extraction stops and whatever it
+/// wrote is removed again.
+pub(crate) fn process_file(
+    path: &Path,
+    profile: &Profile,
+    worker_id: usize,
+    cancel: &CancelToken,
+    progress: &ProgressSink,
+) -> Result<Report, ProcessError> {
+    let partial = profile.partial_rules();
+    if path.file_name().and_then(|s| s.to_str()).is_some_and(|name| partial.matches_name(name)) {
+        return Err(ProcessError::NotAnArchive);
+    }
+    if !path.is_file() {
+        return Err(ProcessError::Missing);
+    }
+    if let Some(sidecar) = partial.sidecar_of(path) {
+        println!(
+            "[Worker {}] {} is still being downloaded ({} exists). Leaving it for now.",
+            worker_id,
+            path.display(),
+            sidecar.display()
+        );
+        return Err(ProcessError::Incomplete);
+    }
+    let stability_timeout = Duration::from_secs(profile.stability_timeout);
+    // Uploads from other hosts only show in the file's size and time.
+    let settle = profile.polls().then(|| Duration::from_secs(profile.poll_interval.max(1)));
+    if let Err(e) = wait_until_stable(path, stability_timeout, settle, cancel) {
+        if cancel.is_cancelled() {
+            return Err(ProcessError::Extract(ExtractError::Cancelled));
+        }
+        eprintln!(
+            "[Worker {}] Skipping {} due to stability check error: {}",
+            worker_id,
+            path.display(),
+            e
+        );
+        return Err(ProcessError::Unstable(e));
+    }
+    let Some((ext, extractor)) = extractors::for_path(path) else {
+        return Err(ProcessError::NotAnArchive);
+    };
+    println!(
+        "[Worker {}] Processing {} file: {}",
+        worker_id,
+        ext,
+        path.display()
+    );
+    let report = |outcome, extraction| Report {
+        archive: path.to_path_buf(),
+        format: ext.clone(),
+        outcome,
+        extraction,
+    };
+
+    let base = match profile.dest_base(path, &ext) {
+        Ok(base) => base,
+        Err(e) => {
+            eprintln!(
+                "[Worker {}] Invalid destination template for {}: {}",
+                worker_id,
+                path.display(),
+                e
+            );
+            return Err(ProcessError::Destination(e));
+        }
+    };
+    if profile.skip_identical {
+        let identical = extractor
+            .entries(path)
+            .and_then(|entries| Ok(find_identical_copy(&base, &profile.collision_suffix, &entries, &profile.extract_options())?));
+        match identical {
+            Ok(Some(existing)) => {
+                println!(
+                    "[Worker {}] {} is a duplicate of already extracted {}. Skipping extraction.",
+                    worker_id,
+                    path.display(),
+                    existing.display()
+                );
+                if profile.dry_run {
+                    println!("[Worker {}] Dry run: would delete {}", worker_id, path.display());
+                } else if !profile.keep_archive {
+                    delete_file(path, worker_id);
+                }
+                return Ok(report(Outcome::Duplicate(existing), None));
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!(
+                "[Worker {}] Could not compare {} with earlier extractions: {}",
+                worker_id,
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    if profile.dry_run {
+        return plan(path, &base, profile, extractor.as_ref(), worker_id).map(|dir| report(Outcome::Planned(dir), None));
+    }
+    let dest = match Destination::prepare(&base, &profile.collision_suffix, profile.on_existing, profile.merge_rule) {
+        Ok(dest) => dest,
+        Err(e) => {
+            eprintln!(
+                "[Worker {}] Failed to prepare destination directory for {}: {}",
+                worker_id,
+                path.display(),
+                e
+            );
+            return Err(ProcessError::Destination(e));
+        }
+    };
+    let options = ExtractOptions {
+        cancel: cancel.clone(),
+        progress: progress.clone(),
+        ..profile.extract_options()
+    };
+    let mut extraction = match extractor.extract(path, dest.work_dir(), worker_id, &options) {
+        Ok(extraction) => extraction,
+        Err(e) => {
+            eprintln!(
+                "[Worker {}] Error extracting {}: {}",
+                worker_id,
+                path.display(),
+                e
+            );
+            dest.discard();
+            return Err(ProcessError::Extract(e));
+        }
+    };
+    if profile.nested_depth > 0 {
+        if let Err(e) = nested::extract_nested(dest.work_dir(), profile, worker_id, &options, &mut extraction) {
+            eprintln!(
+                "[Worker {}] Error extracting nested archives of {}: {}",
+                worker_id,
+                path.display(),
+                e
+            );
+            dest.discard();
+            return Err(ProcessError::Extract(ExtractError::from(e)));
+        }
+    }
+    if cancel.is_cancelled() {
+        eprintln!(
+            "[Worker {}] Extraction of {} was cancelled. Removing its output.",
+            worker_id,
+            path.display()
+        );
+        dest.discard();
+        return Err(ProcessError::Extract(ExtractError::Cancelled));
+    }
+    log_report(&extraction, worker_id);
+    let dest_dir = dest.dir().to_path_buf();
+    if let Err(e) = dest.commit() {
+        eprintln!(
+            "[Worker {}] Failed to move extracted files into {}: {}",
+            worker_id,
+            dest_dir.display(),
+            e
+        );
+        return Err(ProcessError::Destination(e));
+    }
+    if let Err(e) = wait_until_stable(path, stability_timeout, settle, cancel) {
+        eprintln!(
+            "[Worker {}] Failed to achieve stability on {}: {}",
+            worker_id,
+            path.display(),
+            e
+        );
+        return Ok(report(Outcome::Extracted(dest_dir), Some(extraction)));
+    }
+    // Every entry being skipped usually means the policies are off; keep the
+    // archive so nothing is lost.
+    if extraction.entries_written == 0 && !extraction.skipped.is_empty() {
+        println!(
+            "[Worker {}] Nothing was written from {}. Keeping the archive.",
+            worker_id,
+            path.display()
+        );
+    } else if !profile.keep_archive {
+        delete_file(path, worker_id);
+    }
+    Ok(report(Outcome::Extracted(dest_dir), Some(extraction)))
+}
+
+fn log_report(extraction: &ExtractReport, worker_id: usize) {
+    println!(
+        "[Worker {}] Wrote {} files ({} bytes) from {} bytes of {} in {:.2}s; {} entries skipped.",
+        worker_id,
+        extraction.entries_written,
+        extraction.bytes_out,
+        extraction.bytes_in,
+        extraction.format,
+        extraction.elapsed.as_secs_f64(),
+        extraction.skipped.len()
+    );
+    for warning in &extraction.warnings {
+        eprintln!("[Worker {}] Warning: {}", worker_id, warning);
+    }
+}
+
+/// Logs what `process_file` would do for `path` without touching the disk.
+/// Fails where the real run would fail before writing anything.
+fn plan(
+    path: &Path,
+    base: &Path,
+    profile: &Profile,
+    extractor: &dyn ArchiveExtractor,
+    worker_id: usize,
+) -> Result<PathBuf, ProcessError> {
+    let dest = Destination::plan(base, &profile.collision_suffix, profile.on_existing);
+    let action = match profile.on_existing {
+        ExistingPolicy::Merge if dest.exists() => "merge into existing",
+        ExistingPolicy::Replace if dest.exists() => "replace existing",
+        _ => "extract into",
+    };
+    println!(
+        "[Worker {}] Dry run: would {} {} from {}",
+        worker_id,
+        action,
+        dest.display(),
+        path.display()
+    );
+    let entries = extractor.entries(path).map_err(|e| {
+        println!("[Worker {}] Dry run: extraction would fail: {}", worker_id, e);
+        ProcessError::Extract(e)
+    })?;
+    let files = entries.iter().filter(|e| !e.is_dir).count();
+    let bytes: u64 = entries.iter().map(|e| e.size).sum();
+    println!(
+        "[Worker {}] Dry run: {} files, {} bytes unpacked",
+        worker_id, files, bytes
+    );
+    if let Err(e) = profile.extract_options().size_limit().check_declared(&entries) {
+        println!("[Worker {}] Dry run: extraction would fail: {}", worker_id, e);
+        return Err(ProcessError::Extract(e.into()));
+    }
+    if dest.exists() {
+        match profile.on_existing {
+            ExistingPolicy::Replace => {
+                println!("[Worker {}] Dry run: would delete {}", worker_id, dest.display());
+            }
+            ExistingPolicy::Merge => {
+                for target in merge_overwrites(&dest, profile.merge_rule, &entries) {
+                    println!("[Worker {}] Dry run: would overwrite {}", worker_id, target.display());
+                }
+            }
+            ExistingPolicy::NewFolder => {}
+        }
+    }
+    if profile.nested_depth > 0 {
+        for entry in entries.iter().filter(|e| extractors::for_path(&e.path).is_some()) {
+            println!(
+                "[Worker {}] Dry run: would extract nested archive {}",
+                worker_id,
+                entry.path.display()
+            );
+        }
+    }
+    if !profile.keep_archive {
+        println!("[Worker {}] Dry run: would delete {}", worker_id, path.display());
+    }
+    Ok(dest)
+}
+
+/// Extracts a single archive with the settings of `options`, exactly like
+/// the daemon does for a file that appears in a watched folder.
+pub fn extract_archive(path: &Path, options: &Profile) -> Result<Report, ProcessError> {
+    extract_archive_with_progress(path, options, &ProgressSink::default())
+}
+
+/// Like [`extract_archive`], passing updates on how far the extraction has
+/// got to `progress`.
+pub fn extract_archive_with_progress(path: &Path, options: &Profile, progress: &ProgressSink) -> Result<Report, ProcessError> {
+    process_file(path, options, 0, &CancelToken::default(), progress)
+}
+
+/// Looks up the extractor `process_file` would use for `path`.
+pub fn inspect(path: &Path) -> Result<(String, Box<dyn ArchiveExtractor>), ProcessError> {
+    if !path.is_file() {
+        return Err(ProcessError::Missing);
+    }
+    extractors::for_path(path).ok_or(ProcessError::NotAnArchive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dest::{unique_dir, MergeRule};
+    use crate::extractors::collision::CollisionPolicy;
+    use std::io::Write;
+
+    fn temp_dir() -> PathBuf {
+        let mut d = std::env::temp_dir();
+        d.push(format!(
+            "unzipper_test_{}_{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        std::fs::create_dir_all(&d).unwrap();
+        d
+    }
+
+    #[test]
+    fn test_wait_until_stable_on_existing_file() {
+        let mut p = std::env::temp_dir();
+        p.push(format!(
+            "unzipper_test_{}_{}.tmp",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        {
+            let mut f = std::fs::File::create(&p).expect("create temp file");
+            writeln!(f, "hello").unwrap();
+        }
+        let cancel = CancelToken::default();
+        let res = wait_until_stable(&p, Duration::from_secs(2), None, &cancel);
+        assert!(res.is_ok());
+
+        // A file written from another host has to sit still for the whole period.
+        let started = Instant::now();
+        assert!(wait_until_stable(&p, Duration::from_secs(5), Some(Duration::from_millis(700)), &cancel).is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(700));
+        let err = wait_until_stable(&p, Duration::from_millis(500), Some(Duration::from_secs(2)), &cancel).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        std::fs::remove_file(&p).ok();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_wait_until_stable_waits_for_writer() {
+        let td = temp_dir();
+        let p = td.join("download.zip");
+        let mut writer = fs::File::create(&p).unwrap();
+        writer.write_all(b"partial").unwrap();
+        let cancel = CancelToken::default();
+        let err = wait_until_stable(&p, Duration::from_millis(700), None, &cancel).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+
+        // Shutting down doesn't wait for the writer.
+        let cancel = CancelToken::new();
+        let canceller = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(300));
+                cancel.cancel();
+            })
+        };
+        let started = Instant::now();
+        let err = wait_until_stable(&p, Duration::from_secs(30), None, &cancel).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+        let profile = Profile {
+            stability_timeout: 30,
+            ..Profile::default()
+        };
+        assert!(matches!(
+            process_file(&p, &profile, 1, &cancel, &ProgressSink::default()),
+            Err(ProcessError::Extract(ExtractError::Cancelled))
+        ));
+        assert!(p.is_file());
+
+        let closer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            writer.write_all(b" and the rest").unwrap();
+            drop(writer);
+        });
+        let started = Instant::now();
+        assert!(wait_until_stable(&p, Duration::from_secs(5), None, &CancelToken::default()).is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        closer.join().unwrap();
+        fs::remove_dir_all(&td).ok();
+    }
+
+    fn create_sample_zip(dir: &Path) -> PathBuf {
+        let zip_path = dir.join("sample.zip");
+        let file_path = dir.join("inner.txt");
+        std::fs::write(&file_path, b"hi").unwrap();
+        let f = std::fs::File::create(&zip_path).unwrap();
+        let mut z = zip::ZipWriter::new(f);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        z.start_file("inner.txt", options).unwrap();
+        z.write_all(b"hi").unwrap();
+        z.finish().unwrap();
+        zip_path
+    }
+
+    #[test]
+    fn test_find_unique_dest_dir_with_conflict() {
+        let td = temp_dir();
+        let conflicting_dir = td.join("test_archive");
+        fs::create_dir(&conflicting_dir).unwrap();
+        let archive_path = td.join("test_archive.zip");
+        let profile = Profile::default();
+        let unique_path = unique_dir(&profile.dest_base(&archive_path, "zip").unwrap(), &profile.collision_suffix);
+        assert_eq!(unique_path, td.join("test_archive (1)"));
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_zip_extracts_and_deletes_archive() {
+        let td = temp_dir();
+        let zip_path = create_sample_zip(&td);
+        process_file(&zip_path, &Profile::default(), 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        assert!(!zip_path.is_file());
+        let extracted_dir = td.join("sample");
+        assert!(extracted_dir.exists());
+        let inner = extracted_dir.join("inner.txt");
+        assert_eq!(std::fs::read_to_string(inner).unwrap(), "hi");
+        std::fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_zip_renames_case_insensitive_duplicates() {
+        let td = temp_dir();
+        let zip_path = td.join("dupes.zip");
+        let f = std::fs::File::create(&zip_path).unwrap();
+        let mut z = zip::ZipWriter::new(f);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        z.start_file("README.txt", options).unwrap();
+        z.write_all(b"upper").unwrap();
+        z.start_file("readme.txt", options).unwrap();
+        z.write_all(b"lower").unwrap();
+        z.finish().unwrap();
+        let profile = Profile {
+            on_collision: CollisionPolicy::Rename,
+            case_insensitive: true,
+            ..Profile::default()
+        };
+        process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        let extracted_dir = td.join("dupes");
+        assert_eq!(std::fs::read_to_string(extracted_dir.join("README.txt")).unwrap(), "upper");
+        assert_eq!(std::fs::read_to_string(extracted_dir.join("readme (2).txt")).unwrap(), "lower");
+        std::fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_reports_skipped_duplicates() {
+        let td = temp_dir();
+        let zip_path = td.join("dupes.zip");
+        let mut z = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        z.start_file("a.txt", options).unwrap();
+        z.write_all(b"first").unwrap();
+        z.start_file("a.txt", options).unwrap();
+        z.write_all(b"second").unwrap();
+        z.finish().unwrap();
+        let profile = Profile {
+            on_collision: CollisionPolicy::KeepFirst,
+            ..Profile::default()
+        };
+        let report = process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        let extraction = report.extraction.unwrap();
+        assert_eq!(extraction.format, "zip");
+        assert_eq!((extraction.entries_written, extraction.bytes_out), (1, 5));
+        assert_eq!(extraction.skipped[0].name, "a.txt");
+        assert_eq!(extraction.skipped[0].reason, extractors::SkipReason::Duplicate);
+        assert!(extraction.bytes_in > 0);
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_merges_into_existing_folder() {
+        let td = temp_dir();
+        let existing = td.join("sample");
+        fs::create_dir(&existing).unwrap();
+        fs::write(existing.join("inner.txt"), b"local").unwrap();
+        fs::write(existing.join("notes.txt"), b"keep").unwrap();
+        let zip_path = create_sample_zip(&td);
+        let profile = Profile {
+            on_existing: ExistingPolicy::Merge,
+            merge_rule: MergeRule::Skip,
+            ..Profile::default()
+        };
+        process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        assert!(!td.join("sample (1)").exists());
+        assert_eq!(fs::read_to_string(existing.join("inner.txt")).unwrap(), "local");
+        assert_eq!(fs::read_to_string(existing.join("notes.txt")).unwrap(), "keep");
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_replaces_existing_folder() {
+        let td = temp_dir();
+        let existing = td.join("sample");
+        fs::create_dir(&existing).unwrap();
+        fs::write(existing.join("stale.txt"), b"old").unwrap();
+        let zip_path = create_sample_zip(&td);
+        let profile = Profile {
+            on_existing: ExistingPolicy::Replace,
+            ..Profile::default()
+        };
+        process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        assert!(!existing.join("stale.txt").exists());
+        assert_eq!(fs::read_to_string(existing.join("inner.txt")).unwrap(), "hi");
+        let hidden = fs::read_dir(&td).unwrap().filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().starts_with('.'));
+        assert_eq!(hidden.count(), 0);
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_skips_identical_copy() {
+        let td = temp_dir();
+        let profile = Profile {
+            skip_identical: true,
+            ..Profile::default()
+        };
+        let zip_path = create_sample_zip(&td);
+        process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        let zip_path = create_sample_zip(&td);
+        let outcome = process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        assert_eq!(outcome.outcome, Outcome::Duplicate(td.join("sample")));
+        assert!(!zip_path.is_file());
+        assert!(!td.join("sample (1)").exists());
+
+        fs::write(td.join("sample").join("inner.txt"), b"ho").unwrap();
+        let zip_path = create_sample_zip(&td);
+        process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        assert!(td.join("sample (1)").exists());
+
+        // Off by default.
+        let zip_path = create_sample_zip(&td);
+        process_file(&zip_path, &Profile::default(), 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        assert!(td.join("sample (2)").exists());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_uses_dest_template_and_output_dir() {
+        let td = temp_dir();
+        let out = td.join("out");
+        fs::create_dir_all(out.join("zip").join("sample")).unwrap();
+        let zip_path = create_sample_zip(&td);
+        let profile = Profile {
+            output_dir: Some(out.clone()),
+            dest_template: String::from("{format}/{stem}"),
+            collision_suffix: String::from(" ({n:03})"),
+            ..Profile::default()
+        };
+        process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        let inner = out.join("zip").join("sample (001)").join("inner.txt");
+        assert_eq!(fs::read_to_string(inner).unwrap(), "hi");
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_extracts_nested_archives() {
+        let td = temp_dir();
+        let inner_zip = create_sample_zip(&td);
+        let outer_path = td.join("outer.zip");
+        let mut z = zip::ZipWriter::new(fs::File::create(&outer_path).unwrap());
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        z.start_file("bundle/sample.zip", options).unwrap();
+        z.write_all(&fs::read(&inner_zip).unwrap()).unwrap();
+        z.finish().unwrap();
+        fs::remove_file(&inner_zip).unwrap();
+
+        let profile = Profile {
+            nested_depth: 1,
+            ..Profile::default()
+        };
+        process_file(&outer_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        let bundle = td.join("outer").join("bundle");
+        assert!(!bundle.join("sample.zip").exists());
+        assert_eq!(fs::read_to_string(bundle.join("sample").join("inner.txt")).unwrap(), "hi");
+
+        // The budget holds the outer archive to it as well.
+        let profile = Profile {
+            nested_depth: 1,
+            nested_budget: Some(10),
+            ..Profile::default()
+        };
+        let zip_path = td.join("big.zip");
+        let mut z = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        z.start_file("big.txt", options).unwrap();
+        z.write_all(&[0u8; 64]).unwrap();
+        z.finish().unwrap();
+        let result = process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default());
+        assert!(matches!(result, Err(ProcessError::Extract(ExtractError::LimitExceeded))));
+        assert!(!td.join("big").exists());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_enforces_unpacked_size_limit() {
+        let td = temp_dir();
+        let zip_path = create_sample_zip(&td);
+        let profile = Profile {
+            max_unpacked_bytes: Some(1),
+            ..Profile::default()
+        };
+        assert!(matches!(
+            process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()),
+            Err(ProcessError::Extract(ExtractError::LimitExceeded))
+        ));
+        assert!(zip_path.is_file());
+        assert!(!td.join("sample").exists());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_cancelled_removes_output() {
+        let td = temp_dir();
+        let zip_path = create_sample_zip(&td);
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            process_file(&zip_path, &Profile::default(), 1, &cancel, &ProgressSink::default()),
+            Err(ProcessError::Extract(ExtractError::Cancelled))
+        ));
+        assert!(zip_path.is_file());
+        assert!(!td.join("sample").exists());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_dry_run_writes_nothing() {
+        let td = temp_dir();
+        let zip_path = create_sample_zip(&td);
+        let profile = Profile {
+            dry_run: true,
+            ..Profile::default()
+        };
+        let outcome = process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        assert_eq!(outcome.outcome, Outcome::Planned(td.join("sample")));
+        assert!(zip_path.is_file());
+        assert!(!td.join("sample").exists());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_keeps_archive() {
+        let td = temp_dir();
+        let zip_path = create_sample_zip(&td);
+        let profile = Profile {
+            keep_archive: true,
+            ..Profile::default()
+        };
+        process_file(&zip_path, &profile, 1, &CancelToken::default(), &ProgressSink::default()).unwrap();
+        assert!(zip_path.is_file());
+        assert!(td.join("sample").join("inner.txt").is_file());
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_extractor_test_detects_corrupt_entry() {
+        let td = temp_dir();
+        let zip_path = create_sample_zip(&td);
+        let (_, extractor) = inspect(&zip_path).unwrap();
+        assert_eq!(extractor.test(&zip_path).unwrap(), 1);
+        let entries = extractor.entries(&zip_path).unwrap();
+        assert_eq!(entries[0].path, PathBuf::from("inner.txt"));
+        assert_eq!(entries[0].compressed_size, Some(2));
+
+        let mut bytes = fs::read(&zip_path).unwrap();
+        let data = bytes.windows(11).position(|w| w == b"inner.txthi").unwrap() + 9;
+        bytes[data + 1] = b'o';
+        fs::write(&zip_path, bytes).unwrap();
+        assert!(matches!(extractor.test(&zip_path), Err(ExtractError::Corrupt(_))));
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_process_file_ignores_temp_extensions() {
+        let td = temp_dir();
+        let tmp = td.join("ongoing.zip.part");
+        std::fs::write(&tmp, b"x").unwrap();
+        assert!(matches!(
+            process_file(&tmp, &Profile::default(), 5, &CancelToken::default(), &ProgressSink::default()),
+            Err(ProcessError::NotAnArchive)
+        ));
+        assert!(tmp.exists());
+        std::fs::remove_dir_all(&td).ok();
+    }
+}
@@ -0,0 +1,224 @@
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::dest::{ExistingPolicy, MergeRule, DEFAULT_SUFFIX};
+use crate::extractors::collision::CollisionPolicy;
+use crate::extractors::{CancelToken, ExtractOptions, ProgressSink};
+use crate::partial::{PartialPreset, PartialRules};
+use crate::platform;
+use crate::template::{self, TemplateContext};
+
+/// How a folder is watched.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchBackend {
+    /// Native events, or polling on network and FUSE filesystems.
+    #[default]
+    Auto,
+    /// inotify, FSEvents or ReadDirectoryChangesW.
+    Native,
+    /// Look through the folder every `poll_interval` seconds.
+    Poll,
+}
+
+/// Settings for one watched folder.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+    pub watch_path: PathBuf,
+    pub on_existing: ExistingPolicy,
+    pub merge_rule: MergeRule,
+    pub on_collision: CollisionPolicy,
+    pub case_insensitive: bool,
+    /// Delete the archive without extracting when an identical copy was already extracted.
+    pub skip_identical: bool,
+    /// Leave the archive in place after extracting it.
+    pub keep_archive: bool,
+    /// Root that relative destination templates are resolved against; defaults to the archive's folder.
+    pub output_dir: Option<PathBuf>,
+    /// Destination folder for an archive, see [`template::render`].
+    pub dest_template: String,
+    /// Appended to the destination folder name when it is taken, `{n}` being the counter.
+    pub collision_suffix: String,
+    /// Upper bound for the bytes a single archive may unpack to.
+    pub max_unpacked_bytes: Option<u64>,
+    /// How many levels of archives inside archives to extract; 0 disables it.
+    pub nested_depth: usize,
+    /// Upper bound for the whole extracted tree, the outer archive and nested
+    /// ones included. Bytes are counted as they are written, except for rar,
+    /// where only the sizes the archive declares are checked.
+    pub nested_budget: Option<u64>,
+    /// Only log what would be done, without writing or deleting anything.
+    pub dry_run: bool,
+    /// Where archives that fail to extract are moved; defaults to `.unzipper-quarantine` in the watched folder.
+    pub quarantine_dir: Option<PathBuf>,
+    /// Archives of profiles with a higher priority are extracted first.
+    pub priority: u32,
+    /// Seconds an archive may keep being written to before it is put off for a retry.
+    pub stability_timeout: u64,
+    /// Download tools whose unfinished downloads are left alone.
+    pub partial_presets: Vec<PartialPreset>,
+    /// More names of unfinished downloads, see [`PartialRules`].
+    pub partial_patterns: Vec<String>,
+    /// How changes to the watched folder are noticed.
+    pub watcher: WatchBackend,
+    /// Seconds between two looks through the folder when polling.
+    pub poll_interval: u64,
+    /// Also notice files whose contents changed but whose size and modification time didn't, at the cost of reading them.
+    pub poll_compare_contents: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: String::new(),
+            watch_path: PathBuf::new(),
+            on_existing: ExistingPolicy::default(),
+            merge_rule: MergeRule::default(),
+            on_collision: CollisionPolicy::default(),
+            case_insensitive: false,
+            skip_identical: false,
+            keep_archive: false,
+            output_dir: None,
+            dest_template: String::from("{stem}"),
+            collision_suffix: String::from(DEFAULT_SUFFIX),
+            max_unpacked_bytes: None,
+            nested_depth: 0,
+            nested_budget: None,
+            dry_run: false,
+            quarantine_dir: None,
+            priority: 0,
+            stability_timeout: 60,
+            partial_presets: PartialPreset::ALL.to_vec(),
+            partial_patterns: vec![String::from("*.tmp")],
+            watcher: WatchBackend::default(),
+            poll_interval: 5,
+            poll_compare_contents: false,
+        }
+    }
+}
+
+impl Profile {
+    pub fn extract_options(&self) -> ExtractOptions {
+        ExtractOptions {
+            collisions: self.on_collision,
+            case_insensitive: self.case_insensitive,
+            max_unpacked_bytes: match (self.max_unpacked_bytes, self.nested_budget) {
+                (Some(max), Some(budget)) => Some(max.min(budget)),
+                (max, budget) => max.or(budget),
+            },
+            cancel: CancelToken::default(),
+            progress: ProgressSink::default(),
+        }
+    }
+
+    /// Whether the watched folder is looked through every `poll_interval`
+    /// seconds instead of being watched through native events.
+    pub fn polls(&self) -> bool {
+        match self.watcher {
+            WatchBackend::Native => false,
+            WatchBackend::Poll => true,
+            WatchBackend::Auto => platform::is_network_fs(&self.watch_path).unwrap_or(false),
+        }
+    }
+
+    pub fn partial_rules(&self) -> PartialRules {
+        PartialRules::new(&self.partial_presets, self.partial_patterns.iter().map(String::as_str))
+    }
+
+    /// The destination folder for `archive` before any collision suffix is added.
+    pub fn dest_base(&self, archive: &Path, format: &str) -> io::Result<PathBuf> {
+        let root = match &self.output_dir {
+            Some(dir) => dir.clone(),
+            None => archive.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+        };
+        let ctx = TemplateContext::for_archive(archive, format);
+        template::render(&self.dest_template, &ctx, &root)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(rename = "profile")]
+    profiles: Vec<Profile>,
+}
+
+/// Reads the `[[profile]]` tables of a TOML config file.
+pub fn load_profiles(path: &Path) -> io::Result<Vec<Profile>> {
+    let text = fs::read_to_string(path)?;
+    let config: Config = toml::from_str(&text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let mut profiles = config.profiles;
+    for (i, profile) in profiles.iter_mut().enumerate() {
+        if profile.watch_path.as_os_str().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("profile #{} has no watch_path", i + 1),
+            ));
+        }
+        profile.watch_path = platform::expand_home(&profile.watch_path);
+        profile.output_dir = profile.output_dir.as_deref().map(platform::expand_home);
+        profile.quarantine_dir = profile.quarantine_dir.as_deref().map(platform::expand_home);
+        if profile.name.is_empty() {
+            profile.name = profile.watch_path.display().to_string();
+        }
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let config: Config = toml::from_str(
+            r#"
+            [[profile]]
+            name = "bundles"
+            watch_path = "/srv/inbox"
+            on_existing = "merge"
+            merge_rule = "newer"
+            on_collision = "keep-first"
+            output_dir = "/srv/extracted"
+            dest_template = "{date:%Y-%m}/{stem}"
+            collision_suffix = " ({n:03})"
+            watcher = "poll"
+            poll_interval = 30
+
+            [[profile]]
+            watch_path = "/home/me/Downloads"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.profiles.len(), 2);
+        assert_eq!(config.profiles[0].on_existing, ExistingPolicy::Merge);
+        assert_eq!(config.profiles[0].merge_rule, MergeRule::Newer);
+        assert_eq!(config.profiles[0].on_collision, CollisionPolicy::KeepFirst);
+        assert_eq!(config.profiles[0].watcher, WatchBackend::Poll);
+        assert_eq!(config.profiles[0].poll_interval, 30);
+        assert_eq!(config.profiles[1].on_existing, ExistingPolicy::NewFolder);
+        assert_eq!(config.profiles[1].watcher, WatchBackend::Auto);
+    }
+
+    #[test]
+    fn test_backend_selection() {
+        let mut profile = Profile {
+            watch_path: std::env::temp_dir(),
+            ..Profile::default()
+        };
+        profile.watcher = WatchBackend::Native;
+        assert!(!profile.polls());
+        profile.watcher = WatchBackend::Poll;
+        assert!(profile.polls());
+        // A local folder, or one whose filesystem can't be told, gets native events.
+        profile.watcher = WatchBackend::Auto;
+        assert!(!profile.polls());
+        profile.watch_path = PathBuf::from("/nonexistent/unzipper");
+        assert!(!profile.polls());
+    }
+}
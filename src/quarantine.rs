@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::extractors::{self, ExtractError};
+use crate::profile::Profile;
+
+/// Folder used inside the watched folder when a profile sets no `quarantine_dir`.
+pub const DEFAULT_DIR: &str = ".unzipper-quarantine";
+
+/// Contents of the `<archive>.json` file written next to a quarantined archive.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct QuarantineRecord {
+    pub original_path: PathBuf,
+    pub format: Option<String>,
+    pub profile: String,
+    /// [`ExtractError::kind`] of the failure.
+    pub error_kind: String,
+    pub error: String,
+    /// RFC 3339 timestamp.
+    pub quarantined_at: String,
+}
+
+/// Where `profile` puts archives that failed to extract.
+pub fn dir_for(profile: &Profile, archive: &Path) -> PathBuf {
+    match &profile.quarantine_dir {
+        Some(dir) => dir.clone(),
+        None if profile.watch_path.as_os_str().is_empty() => {
+            archive.parent().unwrap_or_else(|| Path::new("")).join(DEFAULT_DIR)
+        }
+        None => profile.watch_path.join(DEFAULT_DIR),
+    }
+}
+
+/// `dir/name`, or `dir/stem (N).ext` if that is taken.
+fn free_name(dir: &Path, name: &str) -> PathBuf {
+    let candidate = dir.join(name);
+    if !candidate.exists() {
+        return candidate;
+    }
+    let (stem, ext) = match name.split_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (name, None),
+    };
+    let mut counter = 1;
+    loop {
+        let name = match ext {
+            Some(ext) => format!("{} ({}).{}", stem, counter, ext),
+            None => format!("{} ({})", stem, counter),
+        };
+        let candidate = dir.join(name);
+        if !candidate.exists() {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    // Most likely a different filesystem.
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Moves `archive` into the quarantine folder of `profile` and writes a
+/// sidecar describing `error`. Returns the new location of the archive.
+pub fn quarantine(archive: &Path, profile: &Profile, error: &ExtractError, worker_id: usize) -> io::Result<PathBuf> {
+    let dir = dir_for(profile, archive);
+    let name = archive
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Archive has no file name"))?;
+    if profile.dry_run {
+        println!(
+            "[Worker {}] Dry run: would quarantine {} in {}",
+            worker_id,
+            archive.display(),
+            dir.display()
+        );
+        return Ok(dir.join(name));
+    }
+    fs::create_dir_all(&dir)?;
+    let target = free_name(&dir, name);
+    move_file(archive, &target)?;
+    let record = QuarantineRecord {
+        original_path: archive.to_path_buf(),
+        format: extractors::for_path(archive).map(|(format, _)| format),
+        profile: profile.name.clone(),
+        error_kind: error.kind().to_string(),
+        error: error.to_string(),
+        quarantined_at: chrono::Local::now().to_rfc3339(),
+    };
+    let mut sidecar = target.clone().into_os_string();
+    sidecar.push(".json");
+    let json = serde_json::to_string_pretty(&record).map_err(io::Error::other)?;
+    fs::write(&sidecar, json)?;
+    println!(
+        "[Worker {}] Quarantined {} as {}",
+        worker_id,
+        archive.display(),
+        target.display()
+    );
+    Ok(target)
+}
+
+/// Moves a quarantined archive back to where it was found and removes its
+/// sidecar. Returns the restored path.
+pub fn restore(quarantined: &Path) -> io::Result<PathBuf> {
+    let mut sidecar = quarantined.to_path_buf().into_os_string();
+    sidecar.push(".json");
+    let record: QuarantineRecord = serde_json::from_str(&fs::read_to_string(&sidecar)?).map_err(io::Error::other)?;
+    if record.original_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", record.original_path.display()),
+        ));
+    }
+    move_file(quarantined, &record.original_path)?;
+    fs::remove_file(&sidecar)?;
+    Ok(record.original_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine_writes_sidecar() {
+        let td = std::env::temp_dir().join(format!("unzipper_quarantine_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        let archive = td.join("broken.tar.gz");
+        fs::write(&archive, b"not gzip").unwrap();
+        fs::create_dir_all(td.join(DEFAULT_DIR)).unwrap();
+        fs::write(td.join(DEFAULT_DIR).join("broken.tar.gz"), b"older").unwrap();
+        let profile = Profile {
+            name: String::from("downloads"),
+            watch_path: td.clone(),
+            ..Profile::default()
+        };
+
+        let error = ExtractError::Corrupt(String::from("invalid gzip header"));
+        let target = quarantine(&archive, &profile, &error, 0).unwrap();
+        assert_eq!(target, td.join(DEFAULT_DIR).join("broken (1).tar.gz"));
+        assert!(!archive.exists());
+        let sidecar = fs::read_to_string(td.join(DEFAULT_DIR).join("broken (1).tar.gz.json")).unwrap();
+        let record: QuarantineRecord = serde_json::from_str(&sidecar).unwrap();
+        assert_eq!(record.original_path, archive);
+        assert_eq!(record.format.as_deref(), Some("tar.gz"));
+        assert_eq!(record.error_kind, "corrupt");
+
+        assert_eq!(restore(&target).unwrap(), archive);
+        assert_eq!(fs::read(&archive).unwrap(), b"not gzip");
+        assert!(!td.join(DEFAULT_DIR).join("broken (1).tar.gz.json").exists());
+        fs::remove_dir_all(&td).ok();
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often, and how patiently, the daemon retries archives that failed
+/// for a reason that may go away by itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first one included; 1 disables retrying.
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled for every further one.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before retrying after `failed` failed attempts, or `None`
+    /// once the attempts are used up.
+    pub fn delay(&self, failed: u32) -> Option<Duration> {
+        if failed == 0 || failed >= self.max_attempts {
+            return None;
+        }
+        let factor = 1u32.checked_shl(failed - 1).unwrap_or(u32::MAX);
+        Some(self.initial_delay.saturating_mul(factor).min(self.max_delay))
+    }
+}
+
+/// Spawns a thread that forwards every `(due, item)` it receives to `out`
+/// once `due` has passed. Items still waiting at shutdown are dropped.
+pub(crate) fn spawn_delay_queue<T: Send + 'static>(
+    out: mpsc::Sender<T>,
+    shutting_down: Arc<AtomicBool>,
+) -> mpsc::Sender<(Instant, T)> {
+    let (tx, rx) = mpsc::channel::<(Instant, T)>();
+    thread::spawn(move || {
+        let mut waiting: Vec<(Instant, T)> = Vec::new();
+        while !shutting_down.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            let timeout = waiting
+                .iter()
+                .map(|(due, _)| due.saturating_duration_since(now))
+                .min()
+                .unwrap_or(Duration::MAX)
+                .min(Duration::from_millis(200));
+            match rx.recv_timeout(timeout) {
+                Ok(item) => waiting.push(item),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) if waiting.is_empty() => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+            }
+            let now = Instant::now();
+            let mut i = 0;
+            while i < waiting.len() {
+                if waiting[i].0 <= now {
+                    let (_, item) = waiting.swap_remove(i);
+                    if out.send(item).is_err() {
+                        return;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(5),
+        };
+        assert_eq!(policy.delay(1), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay(2), Some(Duration::from_secs(4)));
+        assert_eq!(policy.delay(3), Some(Duration::from_secs(5)));
+        assert_eq!(policy.delay(5), None);
+    }
+
+    #[test]
+    fn test_delay_queue_releases_when_due() {
+        let (out, rx) = mpsc::channel();
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let queue = spawn_delay_queue(out, Arc::clone(&shutting_down));
+        let start = Instant::now();
+        queue.send((start + Duration::from_millis(300), "late")).unwrap();
+        queue.send((start + Duration::from_millis(50), "early")).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "early");
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "late");
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        shutting_down.store(true, Ordering::SeqCst);
+    }
+}
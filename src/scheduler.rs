@@ -0,0 +1,358 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::dispatch::Job;
+
+/// Worker count used when none is configured: one per core, within 2..=8
+/// since extraction is as much disk- as CPU-bound.
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get()).clamp(2, 8)
+}
+
+/// Archives of at least `threshold` bytes count as large; at most
+/// `max_concurrent` of them are extracted at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LargeArchives {
+    pub threshold: u64,
+    pub max_concurrent: usize,
+}
+
+impl Default for LargeArchives {
+    fn default() -> Self {
+        LargeArchives {
+            threshold: 256 * 1024 * 1024,
+            max_concurrent: 1,
+        }
+    }
+}
+
+struct Queued {
+    /// Profile priority, then fresh archives before retries.
+    rank: (u32, bool),
+    seq: u64,
+    job: Job,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    /// Higher rank first, then first come first served.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank.cmp(&other.rank).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+struct Queue {
+    small: BinaryHeap<Queued>,
+    large: BinaryHeap<Queued>,
+    seq: u64,
+    large_running: usize,
+    closed: bool,
+}
+
+impl Queue {
+    fn len(&self) -> usize {
+        self.small.len() + self.large.len()
+    }
+
+    /// Whether the next job comes from the large lane. At equal rank the job
+    /// queued first wins, so small archives can't starve a large one.
+    fn next_is_large(&self, large_allowed: bool) -> Option<bool> {
+        match (self.small.peek(), self.large.peek()) {
+            (Some(small), Some(large)) => Some(large_allowed && large > small),
+            (Some(_), None) => Some(false),
+            (None, Some(_)) if large_allowed => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Bounded priority queue between the dispatcher and the workers. Large
+/// archives queue in a lane of their own and never occupy every worker, so
+/// small ones keep flowing behind a multi-gigabyte download.
+pub(crate) struct Scheduler {
+    queue: Mutex<Queue>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    large: LargeArchives,
+}
+
+/// Holds one of the large lane's places while a large archive is extracted.
+pub(crate) struct LargeSlot(Arc<Scheduler>);
+
+impl Drop for LargeSlot {
+    fn drop(&mut self) {
+        self.0.lock().large_running -= 1;
+        self.0.not_empty.notify_all();
+    }
+}
+
+impl Scheduler {
+    pub fn new(capacity: usize, workers: usize, large: LargeArchives) -> Arc<Self> {
+        Arc::new(Scheduler {
+            queue: Mutex::new(Queue {
+                small: BinaryHeap::new(),
+                large: BinaryHeap::new(),
+                seq: 0,
+                large_running: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            large: LargeArchives {
+                max_concurrent: large.max_concurrent.min(workers.saturating_sub(1)).max(1),
+                ..large
+            },
+        })
+    }
+
+    /// A worker panicking never leaves the queue itself inconsistent.
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues `job`, waiting while the queue is full. Hands the job back if
+    /// the scheduler was closed.
+    pub fn push(&self, job: Job) -> Result<(), Job> {
+        let size = fs::metadata(&job.path).map_or(0, |m| m.len());
+        let rank = (job.profile.priority, job.failures == 0);
+        let mut queue = self.lock();
+        while queue.len() >= self.capacity && !queue.closed {
+            queue = self.not_full.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+        if queue.closed {
+            return Err(job);
+        }
+        let seq = queue.seq;
+        queue.seq += 1;
+        let queued = Queued { rank, seq, job };
+        if size >= self.large.threshold {
+            queue.large.push(queued);
+        } else {
+            queue.small.push(queued);
+        }
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// The most urgent job a worker may start now, or `None` if none became
+    /// available within `timeout` or the scheduler was closed.
+    pub fn pop(self: &Arc<Self>, timeout: Duration) -> Option<Job> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.lock();
+        loop {
+            if queue.closed {
+                return None;
+            }
+            let large_allowed = queue.large_running < self.large.max_concurrent;
+            match queue.next_is_large(large_allowed) {
+                Some(true) => {
+                    let mut job = queue.large.pop().expect("peeked").job;
+                    queue.large_running += 1;
+                    job.slot = Some(LargeSlot(Arc::clone(self)));
+                    self.not_full.notify_one();
+                    return Some(job);
+                }
+                Some(false) => {
+                    let job = queue.small.pop().expect("peeked").job;
+                    self.not_full.notify_one();
+                    return Some(job);
+                }
+                None => {}
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return None;
+            }
+            queue = self
+                .not_empty
+                .wait_timeout(queue, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Drops queued jobs and wakes everyone waiting.
+    pub fn close(&self) {
+        let mut queue = self.lock();
+        queue.closed = true;
+        queue.small.clear();
+        queue.large.clear();
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    /// Jobs waiting for a worker.
+    pub fn queued(&self) -> usize {
+        self.lock().len()
+    }
+}
+
+/// Worker threads that are started again when they die from a panic.
+pub(crate) struct WorkerPool<F: Fn(usize) -> JoinHandle<()>> {
+    spawn: F,
+    handles: Vec<Option<JoinHandle<()>>>,
+}
+
+impl<F: Fn(usize) -> JoinHandle<()>> WorkerPool<F> {
+    pub fn start(workers: usize, spawn: F) -> Self {
+        let handles = (0..workers).map(|i| Some(spawn(i))).collect();
+        WorkerPool { spawn, handles }
+    }
+
+    /// Reaps finished workers and restarts the ones that panicked.
+    pub fn supervise(&mut self) {
+        for (i, slot) in self.handles.iter_mut().enumerate() {
+            if !slot.as_ref().is_some_and(|h| h.is_finished()) {
+                continue;
+            }
+            let handle = slot.take().expect("checked above");
+            if handle.join().is_err() {
+                eprintln!("[Main] Worker {} panicked. Restarting it.", i);
+                *slot = Some((self.spawn)(i));
+            }
+        }
+    }
+
+    /// Workers that haven't exited yet.
+    pub fn running(&self) -> usize {
+        self.handles.iter().flatten().filter(|h| !h.is_finished()).count()
+    }
+
+    /// Waits for all workers to exit.
+    pub fn join(self) {
+        for handle in self.handles.into_iter().flatten() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::Profile;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    fn job(path: PathBuf, priority: u32) -> Job {
+        let profile = Profile {
+            priority,
+            ..Profile::default()
+        };
+        Job::new(path, Arc::new(profile))
+    }
+
+    #[test]
+    fn test_scheduler_orders_by_priority_then_arrival() {
+        let scheduler = Scheduler::new(8, 4, LargeArchives::default());
+        assert!(scheduler.push(job(PathBuf::from("a"), 0)).is_ok());
+        assert!(scheduler.push(job(PathBuf::from("b"), 1)).is_ok());
+        assert!(scheduler.push(job(PathBuf::from("c"), 1)).is_ok());
+        assert!(scheduler.push(job(PathBuf::from("d"), 1).retry()).is_ok());
+        let timeout = Duration::from_millis(10);
+        let order: Vec<PathBuf> = (0..4).map(|_| scheduler.pop(timeout).unwrap().path).collect();
+        assert_eq!(order, ["b", "c", "d", "a"].map(PathBuf::from));
+        assert!(scheduler.pop(timeout).is_none());
+        scheduler.close();
+        assert!(scheduler.push(job(PathBuf::from("e"), 0)).is_err());
+    }
+
+    #[test]
+    fn test_scheduler_caps_large_archives() {
+        let td = std::env::temp_dir().join(format!("unzipper_scheduler_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        let file = |name: &str, len: usize| {
+            let path = td.join(name);
+            fs::write(&path, vec![0u8; len]).unwrap();
+            path
+        };
+        let large = LargeArchives {
+            threshold: 100,
+            max_concurrent: 1,
+        };
+        let scheduler = Scheduler::new(8, 4, large);
+        for path in [file("big1.7z", 100), file("big2.7z", 200)] {
+            assert!(scheduler.push(job(path, 0)).is_ok());
+        }
+        let timeout = Duration::from_millis(10);
+        let big = scheduler.pop(timeout).unwrap();
+        assert_eq!(big.path, td.join("big1.7z"));
+        assert!(scheduler.pop(timeout).is_none());
+        // The large lane is busy, so a later small archive overtakes big2.
+        assert!(scheduler.push(job(file("small.zip", 10), 0)).is_ok());
+        assert_eq!(scheduler.pop(timeout).unwrap().path, td.join("small.zip"));
+        assert!(scheduler.pop(timeout).is_none());
+        drop(big);
+        assert_eq!(scheduler.pop(timeout).unwrap().path, td.join("big2.7z"));
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_scheduler_large_archive_not_starved_by_small_ones() {
+        let td = std::env::temp_dir().join(format!("unzipper_scheduler_starve_test_{}", std::process::id()));
+        fs::create_dir_all(&td).unwrap();
+        let file = |name: &str, len: usize| {
+            let path = td.join(name);
+            fs::write(&path, vec![0u8; len]).unwrap();
+            path
+        };
+        let large = LargeArchives {
+            threshold: 100,
+            max_concurrent: 1,
+        };
+        let scheduler = Scheduler::new(8, 4, large);
+        let timeout = Duration::from_millis(10);
+        assert!(scheduler.push(job(file("small0.zip", 10), 0)).is_ok());
+        assert!(scheduler.push(job(file("big.7z", 100), 0)).is_ok());
+        let mut order = Vec::new();
+        // A new small archive of the same priority keeps arriving.
+        for i in 1..4 {
+            assert!(scheduler.push(job(file(&format!("small{}.zip", i), 10), 0)).is_ok());
+            order.push(scheduler.pop(timeout).unwrap().path);
+        }
+        assert_eq!(order[..2], [td.join("small0.zip"), td.join("big.7z")]);
+        fs::remove_dir_all(&td).ok();
+    }
+
+    #[test]
+    fn test_worker_pool_restarts_panicked_worker() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&started);
+        let mut pool = WorkerPool::start(1, move |_| {
+            let first = counter.fetch_add(1, AtomicOrdering::SeqCst) == 0;
+            thread::spawn(move || {
+                if first {
+                    panic!("extractor bug");
+                }
+            })
+        });
+        while started.load(AtomicOrdering::SeqCst) < 2 {
+            pool.supervise();
+            thread::sleep(Duration::from_millis(10));
+        }
+        pool.join();
+        assert_eq!(started.load(AtomicOrdering::SeqCst), 2);
+    }
+}
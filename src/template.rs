@@ -0,0 +1,153 @@
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::platform;
+
+/// Values available to a destination template for one archive.
+pub struct TemplateContext {
+    pub stem: String,
+    pub ext: String,
+    pub format: String,
+    pub mtime: SystemTime,
+    pub domain: Option<String>,
+}
+
+impl TemplateContext {
+    pub fn for_archive(path: &Path, format: &str) -> Self {
+        TemplateContext {
+            stem: path.file_stem().and_then(|s| s.to_str()).unwrap_or("archive").to_string(),
+            ext: path
+                .extension()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .to_ascii_lowercase(),
+            format: format.to_string(),
+            mtime: path
+                .metadata()
+                .and_then(|m| m.modified())
+                .unwrap_or_else(|_| SystemTime::now()),
+            domain: platform::origin_url(path).and_then(|url| domain_of(&url)),
+        }
+    }
+}
+
+/// Strips characters that are invalid in file names on any platform and
+/// escapes a leading `~`, so a placeholder value can never add or escape a
+/// path component, nor be taken for the home directory.
+pub fn sanitize_component(value: &str) -> String {
+    let sanitized: String = value
+        .chars()
+        .filter(|c| !"<>:\"/\\|?*".contains(*c))
+        .collect();
+    if sanitized.chars().all(|c| c == '.') {
+        String::from("archive")
+    } else if let Some(rest) = sanitized.strip_prefix('~') {
+        format!("_{}", rest)
+    } else {
+        sanitized
+    }
+}
+
+fn domain_of(url: &str) -> Option<String> {
+    let rest = url.split_once("://")?.1;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Expands `{stem}`, `{ext}`, `{format}`, `{domain}` and `{date:<strftime>}`
+/// in `template`. A leading `~` is the home directory; relative results are
+/// resolved against `root`.
+pub fn render(template: &str, ctx: &TemplateContext, root: &Path) -> io::Result<PathBuf> {
+    // Only the template's own `~` is expanded, never one a value brings in.
+    let mut out = String::new();
+    let mut rest = template;
+    if Path::new(template).starts_with("~") {
+        out.push_str(&platform::home_dir().to_string_lossy());
+        rest = &template[1..];
+    }
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .map(|i| open + i)
+            .ok_or_else(|| invalid(format!("Unclosed placeholder in template: {}", template)))?;
+        let (name, arg) = match rest[open + 1..close].split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (&rest[open + 1..close], None),
+        };
+        let value = match name {
+            "stem" => ctx.stem.clone(),
+            "ext" => ctx.ext.clone(),
+            "format" => ctx.format.clone(),
+            "domain" => ctx.domain.clone().unwrap_or_else(|| String::from("unknown")),
+            "date" => {
+                let date: chrono::DateTime<chrono::Local> = ctx.mtime.into();
+                let mut s = String::new();
+                write!(s, "{}", date.format(arg.unwrap_or("%Y-%m-%d")))
+                    .map_err(|_| invalid(format!("Invalid date format in template: {}", template)))?;
+                s
+            }
+            _ => return Err(invalid(format!("Unknown placeholder {{{}}} in template", name))),
+        };
+        out.push_str(&sanitize_component(&value));
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    let path = PathBuf::from(out);
+    Ok(if path.is_absolute() { path } else { root.join(path) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn ctx() -> TemplateContext {
+        TemplateContext {
+            stem: String::from("sample-1"),
+            ext: String::from("zip"),
+            format: String::from("zip"),
+            mtime: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            domain: domain_of("https://user@Files.Example.com:8443/dl/sample-1.zip"),
+        }
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        let root = Path::new("/downloads");
+        let path = render("{domain}/{format}/{stem}.{ext}", &ctx(), root).unwrap();
+        assert_eq!(path, PathBuf::from("/downloads/files.example.com/zip/sample-1.zip"));
+        let path = render("/srv/out/{date:%Y}/{stem}", &ctx(), root).unwrap();
+        assert_eq!(path, PathBuf::from("/srv/out/2023/sample-1"));
+    }
+
+    #[test]
+    fn test_render_rejects_unknown_placeholder() {
+        assert!(render("{nope}", &ctx(), Path::new("/")).is_err());
+        assert!(render("{stem", &ctx(), Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn test_placeholder_values_cannot_escape() {
+        let mut ctx = ctx();
+        ctx.stem = String::from("..");
+        let path = render("{stem}", &ctx, Path::new("/downloads")).unwrap();
+        assert_eq!(path, PathBuf::from("/downloads/archive"));
+
+        for stem in ["~", "~root"] {
+            ctx.stem = String::from(stem);
+            let path = render("{stem}", &ctx, Path::new("/downloads")).unwrap();
+            assert_eq!(path, Path::new("/downloads").join(stem.replacen('~', "_", 1)));
+        }
+        let path = render("~/out/{stem}", &ctx, Path::new("/downloads")).unwrap();
+        assert_eq!(path, platform::home_dir().join("out").join("_root"));
+    }
+}